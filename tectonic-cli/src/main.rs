@@ -5,11 +5,21 @@ use rayon::iter::ParallelIterator;
 use rayon::prelude::ParallelBridge;
 use std::{
     fs,
+    io::{Read, Write},
     path::{Path, PathBuf},
 };
-use tectonic::{generate_workload, generate_workload_spec_schema};
+use tectonic::analyze::{AnalyzeOptions, analyze_trace};
+use tectonic::compression::{Compression, WorkloadWriter, decompress, open_workload};
+use tectonic::oracle::{MergeSemantics, StateBoundary};
+use tectonic::spec::RangeFormat;
+use tectonic::split::{SplitBy, SplitOptions};
+use tectonic::{
+    GenerateOptions, ParsedWorkloadSpec, generate_workload, generate_workload_spec_schema,
+    generate_workload_to,
+};
 use tracing::info;
 use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::LevelFilter;
 use walkdir::WalkDir;
 
 #[derive(Parser, Debug)]
@@ -23,60 +33,282 @@ struct Cli {
 enum Command {
     /// Generate workload(s) from a file or folder of workload specifications.
     Generate {
-        /// File or folder of workload spec files
+        /// File or folder of workload spec files, or `-` to read a spec from stdin
         #[arg(short = 'w', long = "workload")]
         workload_path: String,
 
-        /// Output file or folder for workload(s). Defaults to the same directory as the workload spec.
+        /// Output file or folder for workload(s), or `-` for stdout. Defaults to the same directory
+        /// as the workload spec, or stdout for a spec read from stdin.
         #[arg(short = 'o', long = "output", required = false)]
         output: Option<String>,
+
+        /// Multiplies the number of operations of every workload, on top of the `scale` of its spec.
+        /// Output names get a suffix with the resulting scale.
+        #[arg(long = "scale")]
+        scale: Option<f64>,
+
+        /// Also scales the `n` of Zipf key selections and the `amount` of hot ranges.
+        #[arg(long = "scale-parameters", requires = "scale")]
+        scale_parameters: bool,
+
+        /// Memory budget for the valid keys of each workload, e.g. 512M or 8G. Keys beyond it are
+        /// spilled to temporary files.
+        #[arg(long = "max-memory", value_parser = parse_size)]
+        max_memory: Option<usize>,
+
+        /// Seed for the random number generator, overriding the `seed` of the workload spec. The
+        /// same seed always generates the same workload.
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+
+        /// Number of threads that generate the values of each workload. Defaults to the number of
+        /// CPUs. Keys are chosen and formatted on one thread. The workload is the same for any
+        /// number of threads.
+        #[arg(long = "threads")]
+        threads: Option<usize>,
+
+        /// Compresses the output with none, zstd, gzip or lz4. Defaults to the compression of the
+        /// output extension, e.g. `.txt.zst`. Generated output names get its extension.
+        #[arg(long = "compress")]
+        compress: Option<Compression>,
+
+        /// Splits the output into files of at most this many operations, e.g. `name.0.txt`.
+        #[arg(long = "split-ops")]
+        split_ops: Option<usize>,
+
+        /// Splits the output into files of about this many uncompressed bytes, e.g. 1G.
+        #[arg(long = "split-bytes", value_parser = parse_size)]
+        split_bytes: Option<usize>,
+
+        /// Splits the output into a file per section or group, e.g. `name.s0.g1.txt`. A manifest of
+        /// the files and their operation counts is written next to them.
+        #[arg(long = "split-by")]
+        split_by: Option<SplitBy>,
+
+        /// Writes a marker line, e.g. `# section 1 group 0 name=run`, where each section and group
+        /// begins.
+        #[arg(long = "phase-markers")]
+        phase_markers: bool,
+
+        /// Writes the expected result of every operation next to the output, e.g.
+        /// `name.oracle.txt`, one line per line of the workload.
+        #[arg(long = "oracle")]
+        oracle: bool,
+
+        /// How the oracle and state dumps combine merges with the value of their key: replace,
+        /// append or append:<delimiter>.
+        #[arg(long = "merge-semantics", default_value = "replace")]
+        merge_semantics: MergeSemantics,
+
+        /// Writes the live keys in sorted order before the given sections or at the end, e.g.
+        /// `1,end` writes `name.state.s1.txt` and `name.state.end.txt`.
+        #[arg(long = "dump-state", value_delimiter = ',')]
+        dump_state: Vec<StateBoundary>,
+
+        /// Also writes the value of each live key to the state dumps, as `key value`.
+        #[arg(long = "dump-values", requires = "dump_state")]
+        dump_values: bool,
+
+        /// Writes operation counts, key and value lengths, timings and provenance of the workload
+        /// next to the output, e.g. `name.summary.json`.
+        #[arg(long = "summary")]
+        summary: bool,
     },
     /// Prints the JSON schema for IDE integration.
     Schema,
+    /// Characterizes a trace in the ASCII workload format, from tectonic or another generator,
+    /// and prints the analysis as JSON.
+    Analyze {
+        /// Trace file, compressed or not, or `-` to read it from stdin
+        trace: String,
+
+        /// Number of operations per window of the op mix over time.
+        #[arg(long = "window", default_value_t = 100_000)]
+        window: usize,
+
+        /// Number of most popular keys to report.
+        #[arg(long = "top-k", default_value_t = 10)]
+        top_k: usize,
+
+        /// How ranges end, StartCount or StartEnd. By default, an end of only digits is a count.
+        #[arg(long = "range-format")]
+        range_format: Option<RangeFormat>,
+    },
 }
 
 fn main() -> Result<()> {
     let args = Cli::parse();
+    // Logs go to stderr, so that they do not end up in a workload written to stdout. Warnings are
+    // shown unless `RUST_LOG` says otherwise.
     tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::WARN.into())
+                .from_env_lossy(),
+        )
+        .with_writer(std::io::stderr)
         .init();
 
     match args.command {
         Command::Generate {
             workload_path,
             output,
-        } => invoke_generate(&workload_path, output.as_deref()),
+            scale,
+            scale_parameters,
+            max_memory,
+            seed,
+            threads,
+            compress,
+            split_ops,
+            split_bytes,
+            split_by,
+            phase_markers,
+            oracle,
+            merge_semantics,
+            dump_state,
+            dump_values,
+            summary,
+        } => invoke_generate(
+            &workload_path,
+            output.as_deref(),
+            &GenerateOptions {
+                scale,
+                scale_parameters,
+                max_memory,
+                seed,
+                threads,
+                compression: compress,
+                split: SplitOptions {
+                    ops: split_ops,
+                    bytes: split_bytes,
+                    by: split_by,
+                },
+                phase_markers,
+                oracle,
+                merge_semantics,
+                dump_state,
+                dump_values,
+                summary,
+            },
+        ),
         Command::Schema => invoke_schema(),
+        Command::Analyze {
+            trace,
+            window,
+            top_k,
+            range_format,
+        } => invoke_analyze(
+            &trace,
+            &AnalyzeOptions {
+                window,
+                top_k,
+                range_format,
+            },
+        ),
     }
 }
 
-fn spec_path_to_workload_name(spec_path: impl AsRef<Path>) -> String {
-    fn spec_path_to_workload_name_inner(spec_path: &Path) -> String {
+/// Parses a number of bytes with an optional binary suffix, e.g. `512M` or `8G`.
+fn parse_size(size: &str) -> Result<usize> {
+    let size = size.trim();
+    let (number, shift) = match size.char_indices().last() {
+        Some((i, suffix)) if suffix.is_ascii_alphabetic() => {
+            let shift = match suffix.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => bail!("Unknown size suffix in {size}, expected K, M, G or T"),
+            };
+            (&size[..i], shift)
+        }
+        _ => (size, 0),
+    };
+    let number: usize = number
+        .parse()
+        .with_context(|| format!("Parsing size {size}"))?;
+    return number
+        .checked_mul(1 << shift)
+        .with_context(|| format!("Size {size} is too large"));
+}
+
+fn spec_path_to_workload_name(
+    spec_path: impl AsRef<Path>,
+    scale: Option<f64>,
+    compression: Option<Compression>,
+) -> String {
+    fn spec_path_to_workload_name_inner(
+        spec_path: &Path,
+        scale: Option<f64>,
+        compression: Option<Compression>,
+    ) -> String {
+        // Rounded so that products like 0.1 * 3 do not show their floating point error.
+        let suffix = scale.map_or_else(String::new, |scale| {
+            let scale = format!("{scale:.9}");
+            format!(".x{}", scale.trim_end_matches('0').trim_end_matches('.'))
+        });
+        let extension = match compression.and_then(|compression| compression.extension()) {
+            Some(extension) => format!("txt.{extension}"),
+            None => "txt".to_string(),
+        };
         return spec_path
             .file_name()
             .and_then(|stem| stem.to_str())
             .map(|stem| stem.rsplitn(3, '.').collect::<Vec<_>>()[2]) // file.spec.json -> file
-            .map(|stem| format!("{stem}.txt")) // file -> file.txt
+            .map(|stem| format!("{stem}{suffix}.{extension}")) // file -> file.txt, or file.x2.txt.zst
             .unwrap_or_else(|| {
                 let filename = spec_path.file_name().unwrap().to_string_lossy();
                 let basename = filename
                     .rsplit_once('.')
                     .map_or(filename.as_ref(), |(base, _)| base);
-                format!("{basename}.txt")
+                format!("{basename}{suffix}.{extension}")
             });
     }
 
-    return spec_path_to_workload_name_inner(spec_path.as_ref());
+    return spec_path_to_workload_name_inner(spec_path.as_ref(), scale, compression);
+}
+
+/// The path that stands for stdin or stdout.
+const STDIO_PATH: &str = "-";
+
+/// Generates a workload from `contents` into `output`, or into stdout if `output` is `-`.
+fn generate_workload_into(
+    workload_spec: &ParsedWorkloadSpec,
+    output: &Path,
+    options: &GenerateOptions,
+) -> Result<()> {
+    if output == Path::new(STDIO_PATH) {
+        let writer = WorkloadWriter::stdout(options.compression.unwrap_or_default());
+        return generate_workload_to(workload_spec, writer, options);
+    }
+    return generate_workload(workload_spec, output, options);
 }
 
 /// Generate workload(s) from a file or folder of workload specifications.
-fn invoke_generate(workload_path: &str, output: Option<&str>) -> Result<()> {
+fn invoke_generate(
+    workload_path: &str,
+    output: Option<&str>,
+    options: &GenerateOptions,
+) -> Result<()> {
+    if workload_path == STDIO_PATH {
+        let mut contents = String::new();
+        std::io::stdin()
+            .read_to_string(&mut contents)
+            .context("Reading spec from stdin")?;
+        let output = Path::new(output.unwrap_or(STDIO_PATH));
+        let workload_spec = ParsedWorkloadSpec::parse(&contents, options)?;
+        return generate_workload_into(&workload_spec, output, options);
+    }
+
     let workload_path = PathBuf::from(workload_path);
     if !workload_path.exists() {
         bail!("File or folder does not exist {}", workload_path.display());
     }
 
     if workload_path.is_dir() {
+        if output == Some(STDIO_PATH) {
+            bail!("Cannot write the workloads of a folder to stdout");
+        }
         let output_dir = output
             .map(PathBuf::from)
             .unwrap_or_else(|| workload_path.clone());
@@ -105,22 +337,32 @@ fn invoke_generate(workload_path: &str, output: Option<&str>) -> Result<()> {
                 info!("Generating workload for: {}", path.display());
                 let contents = fs::read_to_string(path)?;
 
-                let output_file = spec_path_to_workload_name(path);
+                let workload_spec = ParsedWorkloadSpec::parse(&contents, options)
+                    .with_context(|| format!("Reading {}", path.display()))?;
+                let output_file =
+                    spec_path_to_workload_name(path, workload_spec.scale(), options.compression);
 
                 let mut output_file_path = output_dir.clone();
                 output_file_path.push(output_file);
 
-                return generate_workload(&contents, &output_file_path);
+                return generate_workload(&workload_spec, &output_file_path, options);
             })
             .collect::<Result<Vec<_>>>()?;
     } else if workload_path.is_file() {
-        let output_file = output
-            .map(PathBuf::from)
-            .unwrap_or_else(|| spec_path_to_workload_name(&workload_path).into());
-
         let contents = fs::read_to_string(&workload_path)?;
+        let workload_spec = ParsedWorkloadSpec::parse(&contents, options)?;
 
-        generate_workload(&contents, &output_file)?;
+        let output_file = match output {
+            Some(output) => PathBuf::from(output),
+            None => spec_path_to_workload_name(
+                &workload_path,
+                workload_spec.scale(),
+                options.compression,
+            )
+            .into(),
+        };
+
+        generate_workload_into(&workload_spec, &output_file, options)?;
     } else {
         unreachable!("Path is neither a file nor a directory");
     };
@@ -128,6 +370,20 @@ fn invoke_generate(workload_path: &str, output: Option<&str>) -> Result<()> {
     return Ok(());
 }
 
+/// Analyzes a trace file, or a trace read from stdin, and prints the analysis.
+fn invoke_analyze(trace: &str, options: &AnalyzeOptions) -> Result<()> {
+    let reader = if trace == STDIO_PATH {
+        decompress(std::io::stdin())?
+    } else {
+        open_workload(Path::new(trace))?
+    };
+    let analysis = analyze_trace(reader, options)?;
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &analysis).context("Writing analysis")?;
+    writeln!(stdout)?;
+    return Ok(());
+}
+
 /// Prints the json schema for IDE integration.
 fn invoke_schema() -> Result<()> {
     let schema_str = generate_workload_spec_schema().context("Schema generation failed.")?;
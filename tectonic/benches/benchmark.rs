@@ -1,8 +1,12 @@
 use std::io::{BufWriter, Write};
 
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
 use rand::{Rng, SeedableRng};
 use rand_distr::Alphanumeric;
+use rand_xoshiro::Xoshiro256Plus;
+use tectonic::keyset::{Key, KeySet, OrderStatisticTreeKeySet, SlotMapKeySet, VecOptionKeySet};
+use tectonic::spec::{Selection, SelectionOrder, StringExpr, WorkloadSpec};
+use tectonic::{GenerateOptions, write_operations};
 
 fn buffered_buffered_writer<W: Write>(writer: &mut BufWriter<W>, len: usize) {
     let mut rng = rand_xoshiro::Xoroshiro128PlusPlus::from_seed([
@@ -43,5 +47,119 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// Writes 1 KiB uniform values, the hot path of generating most workloads.
+pub fn value_benchmark(c: &mut Criterion) {
+    let val: StringExpr = serde_json::from_str(r#"{ "uniform": { "len": 1024 } }"#).unwrap();
+    let mut rng = Xoshiro256Plus::seed_from_u64(0);
+    let mut buf = Vec::with_capacity(1024);
+    let mut group = c.benchmark_group("value");
+    group.throughput(Throughput::Bytes(1024));
+    group.bench_function("uniform 1 KiB", |b| {
+        b.iter(|| {
+            buf.clear();
+            val.write_all(&mut buf, &mut rng, None).unwrap();
+            std::hint::black_box(&buf);
+        })
+    });
+    group.finish();
+}
+
+/// Generates example specs end to end on a single thread, scaled down so that an iteration only
+/// takes a moment.
+pub fn workload_benchmark(c: &mut Criterion) {
+    let specs = [
+        (
+            "ycsb a",
+            include_str!("../../example-specs/ycsb/a.spec.json"),
+            0.01,
+        ),
+        (
+            "scale 1m complex",
+            include_str!("../../example-specs/scale/001m-complex.spec.json"),
+            0.01,
+        ),
+    ];
+    let options = GenerateOptions {
+        threads: Some(1),
+        ..Default::default()
+    };
+    let mut group = c.benchmark_group("workload");
+    group.sample_size(10);
+    for (name, spec, scale) in specs {
+        let mut workload: WorkloadSpec = serde_json::from_str(spec).unwrap();
+        workload.scale = Some(scale);
+        workload.seed = Some(0);
+        workload.apply_seed();
+        let mut output = Vec::new();
+        write_operations(&mut output, &workload, &options).unwrap();
+        group.throughput(Throughput::Bytes(output.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| write_operations(&mut std::io::sink(), &workload, &options).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn random_keys(rng: &mut impl Rng, count: usize) -> Vec<Key> {
+    (0..count)
+        .map(|_| rng.sample_iter(Alphanumeric).take(16).collect())
+        .collect()
+}
+
+fn keyset_with_keys<KeySetT: KeySet>(keys: &[Key]) -> KeySetT {
+    let mut keyset = KeySetT::new(keys.len());
+    for key in keys {
+        keyset.push(key.clone());
+    }
+    keyset
+}
+
+/// Interleaves point deletes, point queries and inserts, like a group that mixes them.
+fn mixed_operations<KeySetT: KeySet>(
+    keyset: &mut KeySetT,
+    rng: &mut impl Rng,
+    fresh_keys: &[Key],
+    order: SelectionOrder,
+) {
+    let selection = Selection::default_key_selection();
+    for key in fresh_keys {
+        keyset.remove_random(rng, &selection, order);
+        std::hint::black_box(keyset.get_random(rng, &selection, order));
+        keyset.push(key.clone());
+    }
+}
+
+fn bench_keyset<KeySetT: KeySet>(c: &mut Criterion, name: &str, order: SelectionOrder, ops: usize) {
+    let mut rng = rand_xoshiro::Xoshiro256Plus::seed_from_u64(1);
+    let keys = random_keys(&mut rng, 100_000);
+    let fresh_keys = random_keys(&mut rng, ops);
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || keyset_with_keys::<KeySetT>(&keys),
+            |mut keyset| mixed_operations(&mut keyset, &mut rng, &fresh_keys, order),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+pub fn keyset_benchmark(c: &mut Criterion) {
+    bench_keyset::<VecOptionKeySet>(c, "vec option mixed", SelectionOrder::Hashed, 10_000);
+    bench_keyset::<SlotMapKeySet>(c, "slot map mixed", SelectionOrder::Hashed, 10_000);
+    bench_keyset::<VecOptionKeySet>(c, "vec option mixed sorted", SelectionOrder::Sorted, 100);
+    bench_keyset::<SlotMapKeySet>(c, "slot map mixed sorted", SelectionOrder::Sorted, 100);
+    bench_keyset::<OrderStatisticTreeKeySet>(
+        c,
+        "order statistic tree mixed sorted",
+        SelectionOrder::Sorted,
+        100,
+    );
+}
+
+criterion_group!(
+    benches,
+    criterion_benchmark,
+    value_benchmark,
+    workload_benchmark,
+    keyset_benchmark
+);
 criterion_main!(benches);
@@ -0,0 +1,483 @@
+#![allow(clippy::needless_return)]
+
+use crate::keyset::{Key, KeySet, OrderStatisticTreeKeySet};
+use crate::spec::{Op, RangeFormat};
+use crate::summary::LengthHistogram;
+use crate::trace::{RangeEnd, TraceOp, TraceReader};
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+use tracing::debug;
+
+/// Options for analyzing a trace.
+#[derive(Debug, Clone)]
+pub struct AnalyzeOptions {
+    /// The number of operations per window of the op mix.
+    pub window: usize,
+    /// The number of most popular keys to report.
+    pub top_k: usize,
+    /// How ranges end. Detected from each range when it is not set.
+    pub range_format: Option<RangeFormat>,
+}
+
+impl Default for AnalyzeOptions {
+    fn default() -> Self {
+        return Self {
+            window: 100_000,
+            top_k: 10,
+            range_format: None,
+        };
+    }
+}
+
+/// The characteristics of a trace, in the terms of a workload spec.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct TraceAnalysis {
+    pub operations: usize,
+    /// The number of operations by their field in a spec, e.g. `point_queries`.
+    pub op_counts: BTreeMap<&'static str, usize>,
+    /// The op mix of consecutive windows of operations.
+    pub windows: Vec<OpWindow>,
+    pub key_popularity: KeyPopularity,
+    /// The lengths of the keys that operations name, including the bounds of ranges.
+    pub key_lengths: LengthHistogram,
+    /// The lengths of the values of inserts, updates and merges.
+    pub value_lengths: LengthHistogram,
+    pub insert_sortedness: Sortedness,
+    pub range_query_selectivity: Selectivity,
+    pub range_delete_selectivity: Selectivity,
+    /// Valid keys at the end of the trace.
+    pub live_keys: usize,
+}
+
+/// The op mix of a window of operations.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct OpWindow {
+    /// The index of the first operation of the window.
+    pub start: usize,
+    pub operations: usize,
+    pub op_counts: BTreeMap<&'static str, usize>,
+}
+
+/// How often valid keys are accessed by updates, merges, point deletes and point queries.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct KeyPopularity {
+    pub distinct_keys: usize,
+    /// The most popular keys, most popular first.
+    pub top_keys: Vec<KeyCount>,
+    /// The Zipf distribution that fits the popularity of all keys, unless there are too few.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zipf: Option<ZipfFit>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct KeyCount {
+    pub key: String,
+    pub count: usize,
+}
+
+/// The Zipf distribution over the accessed keys that most likely gave their access counts.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ZipfFit {
+    /// The `s` of the Zipf distribution, 0 for uniform popularity.
+    pub s: f64,
+    /// The `n` of the Zipf distribution, the number of accessed keys.
+    pub n: usize,
+}
+
+/// How far the inserts are from sorted order, as the `k` and `l` of the `sorted` group option.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Sortedness {
+    pub inserts: usize,
+    /// The number of out of place inserts, i.e. those outside a longest sorted subsequence.
+    pub k: usize,
+    /// The largest distance of an out of place insert from its sorted position.
+    pub l_max: usize,
+    /// The mean distance of the out of place inserts from their sorted positions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub l_mean: Option<f64>,
+}
+
+/// The distribution of the fractions of the valid keys that ranges cover.
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct Selectivity {
+    pub count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p90: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+/// Replays a trace against its valid keys, which tells empty point deletes and queries apart and
+/// gives ranges their selectivity.
+struct Analyzer {
+    options: AnalyzeOptions,
+    live: OrderStatisticTreeKeySet,
+    key_counts: HashMap<Key, usize>,
+    inserts: Vec<Key>,
+    op_counts: [usize; Op::ALL.len()],
+    window_counts: [usize; Op::ALL.len()],
+    window_start: usize,
+    windows: Vec<OpWindow>,
+    operations: usize,
+    key_lengths: LengthHistogram,
+    value_lengths: LengthHistogram,
+    range_query_selectivities: Vec<f64>,
+    range_delete_selectivities: Vec<f64>,
+}
+
+/// Analyzes the trace read from `reader`.
+pub fn analyze_trace(reader: impl BufRead, options: &AnalyzeOptions) -> Result<TraceAnalysis> {
+    if options.window == 0 {
+        bail!("Windows must hold at least one operation");
+    }
+    let mut trace = TraceReader::new(reader, options.range_format);
+    let mut analyzer = Analyzer {
+        options: options.clone(),
+        live: OrderStatisticTreeKeySet::new(0),
+        key_counts: HashMap::new(),
+        inserts: Vec::new(),
+        op_counts: [0; Op::ALL.len()],
+        window_counts: [0; Op::ALL.len()],
+        window_start: 0,
+        windows: Vec::new(),
+        operations: 0,
+        key_lengths: LengthHistogram::default(),
+        value_lengths: LengthHistogram::default(),
+        range_query_selectivities: Vec::new(),
+        range_delete_selectivities: Vec::new(),
+    };
+    while let Some(op) = trace.next_op()? {
+        analyzer.apply(op);
+    }
+    debug!(lines = trace.line_number(), "Read trace");
+    return Ok(analyzer.finish());
+}
+
+impl Analyzer {
+    fn apply(&mut self, op: TraceOp) {
+        let op = match op {
+            TraceOp::Insert { key, value } => {
+                self.key_lengths.record(key.len());
+                self.value_lengths.record(value.len());
+                let key = Key::from(key);
+                self.inserts.push(key.clone());
+                self.live.push(key);
+                Op::Insert
+            }
+            TraceOp::Update { key, value } => {
+                self.key_lengths.record(key.len());
+                self.value_lengths.record(value.len());
+                self.count_access(key);
+                Op::Update
+            }
+            TraceOp::Merge { key, value } => {
+                self.key_lengths.record(key.len());
+                self.value_lengths.record(value.len());
+                self.count_access(key);
+                Op::Merge
+            }
+            TraceOp::PointDelete { key } => {
+                self.key_lengths.record(key.len());
+                match self.live_index(key) {
+                    Some(idx) => {
+                        self.count_access(key);
+                        self.live.remove(idx);
+                        Op::PointDelete
+                    }
+                    None => Op::PointDeleteEmpty,
+                }
+            }
+            TraceOp::RangeDelete { start, end } => {
+                if let Some((start_idx, end_idx)) = self.range(start, end, true)
+                    && end_idx > start_idx
+                {
+                    self.live.remove_range(start_idx..end_idx);
+                }
+                Op::RangeDelete
+            }
+            TraceOp::PointQuery { key } => {
+                self.key_lengths.record(key.len());
+                match self.live_index(key) {
+                    Some(_) => {
+                        self.count_access(key);
+                        Op::PointQuery
+                    }
+                    None => Op::EmptyPointQuery,
+                }
+            }
+            TraceOp::RangeQuery { start, end } => {
+                self.range(start, end, false);
+                Op::RangeQuery
+            }
+        };
+        let op_idx = Op::ALL
+            .iter()
+            .position(|&o| o == op)
+            .expect("op to be listed");
+        self.op_counts[op_idx] += 1;
+        self.window_counts[op_idx] += 1;
+        self.operations += 1;
+        if self.operations - self.window_start == self.options.window {
+            self.end_window();
+        }
+    }
+
+    fn count_access(&mut self, key: &[u8]) {
+        match self.key_counts.get_mut(key) {
+            Some(count) => *count += 1,
+            None => {
+                self.key_counts.insert(Key::from(key), 1);
+            }
+        }
+    }
+
+    /// The index of `key` in the valid keys, if it is valid.
+    fn live_index(&self, key: &[u8]) -> Option<usize> {
+        let idx = self.live.rank(key);
+        return (idx < self.live.len() && self.live.get(idx).as_ref() == key).then_some(idx);
+    }
+
+    /// Records the selectivity of a range, returning the indices of the valid keys it covers,
+    /// unless there are none.
+    fn range(&mut self, start: &[u8], end: RangeEnd, is_delete: bool) -> Option<(usize, usize)> {
+        self.key_lengths.record(start.len());
+        if let RangeEnd::Key(end) = end {
+            self.key_lengths.record(end.len());
+        }
+        let len = self.live.len();
+        if len == 0 {
+            return None;
+        }
+        let start_idx = self.live.rank(start);
+        let end_idx = match end {
+            RangeEnd::Count(count) => (start_idx + count).min(len),
+            RangeEnd::Key(end) => self.live.rank(end).max(start_idx),
+        };
+        let selectivities = if is_delete {
+            &mut self.range_delete_selectivities
+        } else {
+            &mut self.range_query_selectivities
+        };
+        selectivities.push((end_idx - start_idx) as f64 / len as f64);
+        return Some((start_idx, end_idx));
+    }
+
+    fn end_window(&mut self) {
+        self.windows.push(OpWindow {
+            start: self.window_start,
+            operations: self.operations - self.window_start,
+            op_counts: named_counts(&self.window_counts),
+        });
+        self.window_counts = [0; Op::ALL.len()];
+        self.window_start = self.operations;
+    }
+
+    fn finish(mut self) -> TraceAnalysis {
+        if self.operations > self.window_start {
+            self.end_window();
+        }
+        return TraceAnalysis {
+            operations: self.operations,
+            op_counts: named_counts(&self.op_counts),
+            windows: self.windows,
+            key_popularity: key_popularity(&self.key_counts, self.options.top_k),
+            key_lengths: self.key_lengths,
+            value_lengths: self.value_lengths,
+            insert_sortedness: sortedness(&self.inserts),
+            range_query_selectivity: selectivity(self.range_query_selectivities),
+            range_delete_selectivity: selectivity(self.range_delete_selectivities),
+            live_keys: self.live.len(),
+        };
+    }
+}
+
+/// The non-zero counts by the name of their operation.
+fn named_counts(counts: &[usize; Op::ALL.len()]) -> BTreeMap<&'static str, usize> {
+    return std::iter::zip(Op::ALL, counts)
+        .filter(|&(_, &count)| count > 0)
+        .map(|(op, &count)| (op.name(), count))
+        .collect();
+}
+
+fn key_popularity(key_counts: &HashMap<Key, usize>, top_k: usize) -> KeyPopularity {
+    let mut counts: Vec<(&Key, usize)> = key_counts
+        .iter()
+        .map(|(key, &count)| (key, count))
+        .collect();
+    counts.sort_unstable_by(|(key_a, count_a), (key_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| key_a.cmp(key_b))
+    });
+    return KeyPopularity {
+        distinct_keys: counts.len(),
+        top_keys: counts
+            .iter()
+            .take(top_k)
+            .map(|(key, count)| KeyCount {
+                key: String::from_utf8_lossy(key).into_owned(),
+                count: *count,
+            })
+            .collect(),
+        zipf: fit_zipf(counts.iter().map(|&(_, count)| count)),
+    };
+}
+
+/// The largest Zipf exponent that is fit.
+const ZIPF_MAX_S: f64 = 16.;
+
+/// Fits the `s` of a Zipf distribution over `n` ranks by maximum likelihood to counts in
+/// decreasing order. The likelihood is concave in `s`, so its derivative is bisected.
+fn fit_zipf(counts: impl ExactSizeIterator<Item = usize> + Clone) -> Option<ZipfFit> {
+    let n = counts.len();
+    if n < 2 {
+        return None;
+    }
+    let total: f64 = counts.clone().sum::<usize>() as f64;
+    // The mean log rank of the accesses, which the fit must match.
+    let observed = counts
+        .enumerate()
+        .map(|(i, count)| count as f64 * ((i + 1) as f64).ln())
+        .sum::<f64>()
+        / total;
+    let expected = |s: f64| {
+        let (weights, weighted_logs) = (1..=n).fold((0., 0.), |(w, wl), rank| {
+            let log_rank = (rank as f64).ln();
+            let weight = (-s * log_rank).exp();
+            (w + weight, wl + weight * log_rank)
+        });
+        return weighted_logs / weights;
+    };
+    let (mut low, mut high) = (0., ZIPF_MAX_S);
+    for _ in 0..50 {
+        let mid = (low + high) / 2.;
+        if expected(mid) > observed {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    return Some(ZipfFit {
+        s: (low + high) / 2.,
+        n,
+    });
+}
+
+/// Measures the `k` and `l` of inserts from a longest non-decreasing subsequence of them.
+fn sortedness(inserts: &[Key]) -> Sortedness {
+    const NONE: usize = usize::MAX;
+    // The last insert of the best subsequence of each length, and the insert before each insert.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![NONE; inserts.len()];
+    for (i, key) in inserts.iter().enumerate() {
+        let pos = tails.partition_point(|&tail| inserts[tail] <= *key);
+        if pos > 0 {
+            prev[i] = tails[pos - 1];
+        }
+        if pos == tails.len() {
+            tails.push(i);
+        } else {
+            tails[pos] = i;
+        }
+    }
+    let mut in_order = vec![false; inserts.len()];
+    let mut i = tails.last().copied().unwrap_or(NONE);
+    while i != NONE {
+        in_order[i] = true;
+        i = prev[i];
+    }
+
+    let mut sorted: Vec<usize> = (0..inserts.len()).collect();
+    sorted.sort_by(|&a, &b| inserts[a].cmp(&inserts[b]));
+    let mut sorted_pos = vec![0; inserts.len()];
+    for (pos, &i) in sorted.iter().enumerate() {
+        sorted_pos[i] = pos;
+    }
+    let distances: Vec<usize> = (0..inserts.len())
+        .filter(|&i| !in_order[i])
+        .map(|i| i.abs_diff(sorted_pos[i]))
+        .collect();
+    return Sortedness {
+        inserts: inserts.len(),
+        k: distances.len(),
+        l_max: distances.iter().copied().max().unwrap_or(0),
+        l_mean: (!distances.is_empty())
+            .then(|| distances.iter().sum::<usize>() as f64 / distances.len() as f64),
+    };
+}
+
+fn selectivity(mut selectivities: Vec<f64>) -> Selectivity {
+    if selectivities.is_empty() {
+        return Selectivity::default();
+    }
+    selectivities.sort_unstable_by(f64::total_cmp);
+    let quantile = |q: f64| {
+        let idx = (q * (selectivities.len() - 1) as f64).round() as usize;
+        return Some(selectivities[idx]);
+    };
+    return Selectivity {
+        count: selectivities.len(),
+        min: quantile(0.),
+        mean: Some(selectivities.iter().sum::<f64>() / selectivities.len() as f64),
+        p50: quantile(0.5),
+        p90: quantile(0.9),
+        p99: quantile(0.99),
+        max: quantile(1.),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<Key> {
+        return keys.iter().map(|key| Key::from(key.as_bytes())).collect();
+    }
+
+    #[test]
+    fn fit_zipf_recovers_s() {
+        let counts: Vec<usize> = (1..=200)
+            .map(|rank| (1e6 / (rank as f64).powf(1.2)).round() as usize)
+            .collect();
+        let fit = fit_zipf(counts.into_iter()).unwrap();
+        assert_eq!(fit.n, 200);
+        assert!((fit.s - 1.2).abs() < 0.01, "s = {}", fit.s);
+
+        let fit = fit_zipf(vec![5; 50].into_iter()).unwrap();
+        assert!(fit.s < 1e-6, "s = {}", fit.s);
+
+        assert!(fit_zipf(vec![7].into_iter()).is_none());
+    }
+
+    #[test]
+    fn sortedness_of_inserts() {
+        let sorted = sortedness(&keys(&["a", "b", "c", "d"]));
+        assert_eq!((sorted.inserts, sorted.k, sorted.l_max), (4, 0, 0));
+        assert_eq!(sorted.l_mean, None);
+
+        // `x` is 5 inserts before its sorted position, and `b` 4 after its own.
+        let shuffled = sortedness(&keys(&["a", "x", "c", "d", "e", "b", "f"]));
+        assert_eq!((shuffled.k, shuffled.l_max), (2, 5));
+        assert_eq!(shuffled.l_mean, Some(4.5));
+
+        let empty = sortedness(&[]);
+        assert_eq!((empty.inserts, empty.k, empty.l_max), (0, 0, 0));
+    }
+
+    #[test]
+    fn range_selectivity_is_clamped_to_the_valid_keys() {
+        let trace = "I a 1\nI b 1\nI c 1\nI d 1\nS b 100\nS a c\nR c 1\n";
+        let analysis = analyze_trace(trace.as_bytes(), &AnalyzeOptions::default()).unwrap();
+        assert_eq!(analysis.range_query_selectivity.max, Some(0.75));
+        assert_eq!(analysis.range_query_selectivity.min, Some(0.5));
+        assert_eq!(analysis.range_delete_selectivity.max, Some(0.25));
+        assert_eq!(analysis.live_keys, 3);
+    }
+}
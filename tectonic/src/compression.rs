@@ -0,0 +1,288 @@
+#![allow(clippy::needless_return)]
+
+use anyhow::{Context, Result, bail};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::JoinHandle;
+
+/// The size of the blocks handed to the compression thread.
+const BLOCK_BYTES: usize = 1024 * 1024;
+/// The number of blocks that can wait for the compression thread before generation blocks.
+const BLOCKS_QUEUED: usize = 8;
+
+/// How a workload file is compressed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Gzip,
+    Lz4,
+}
+
+impl Compression {
+    /// The compression of a file with the given extension, e.g. `.txt.zst`.
+    pub fn from_path(path: &Path) -> Self {
+        return match path.extension().and_then(|extension| extension.to_str()) {
+            Some("zst") => Self::Zstd,
+            Some("gz") => Self::Gzip,
+            Some("lz4") => Self::Lz4,
+            _ => Self::None,
+        };
+    }
+
+    /// The compression of a stream starting with `header`, from the magic number of its format.
+    fn from_header(header: &[u8]) -> Self {
+        return if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if header.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Self::Lz4
+        } else {
+            Self::None
+        };
+    }
+
+    /// The extension appended to the names of compressed files, without the dot.
+    pub fn extension(&self) -> Option<&'static str> {
+        return match self {
+            Self::None => None,
+            Self::Zstd => Some("zst"),
+            Self::Gzip => Some("gz"),
+            Self::Lz4 => Some("lz4"),
+        };
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        return match s {
+            "none" => Ok(Self::None),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "lz4" => Ok(Self::Lz4),
+            _ => bail!("Unknown compression {s}, expected none, zstd, gzip or lz4"),
+        };
+    }
+}
+
+/// A streaming encoder of one of the compressions.
+enum Encoder<W: Write> {
+    Zstd(zstd::Encoder<'static, W>),
+    Gzip(flate2::write::GzEncoder<W>),
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(writer: W, compression: Compression) -> Result<Self> {
+        return Ok(match compression {
+            Compression::None => unreachable!("Uncompressed output is not encoded"),
+            Compression::Zstd => {
+                Self::Zstd(zstd::Encoder::new(writer, 0).context("Creating zstd encoder")?)
+            }
+            // The default level is several times slower than generating the workload.
+            Compression::Gzip => Self::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::fast(),
+            )),
+            Compression::Lz4 => Self::Lz4(lz4_flex::frame::FrameEncoder::new(writer)),
+        });
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        return match self {
+            Self::Zstd(encoder) => encoder.write_all(buf),
+            Self::Gzip(encoder) => encoder.write_all(buf),
+            Self::Lz4(encoder) => encoder.write_all(buf),
+        };
+    }
+
+    /// Writes the end of the stream, returning the underlying writer.
+    fn finish(self) -> Result<W> {
+        return match self {
+            Self::Zstd(encoder) => encoder.finish().context("Finishing zstd stream"),
+            Self::Gzip(encoder) => encoder.finish().context("Finishing gzip stream"),
+            Self::Lz4(encoder) => encoder.finish().context("Finishing lz4 stream"),
+        };
+    }
+}
+
+/// Compresses the blocks it receives into `writer` until the sending side hangs up.
+fn compress_blocks<W: Write>(
+    writer: W,
+    compression: Compression,
+    blocks: Receiver<Vec<u8>>,
+) -> Result<()> {
+    let mut encoder = Encoder::new(writer, compression)?;
+    for block in blocks {
+        encoder
+            .write_all(&block)
+            .context("Writing compressed workload")?;
+    }
+    encoder.finish()?.flush()?;
+    return Ok(());
+}
+
+/// Compresses what is written to it on a separate thread, so that generation only waits for
+/// compression once the queue of blocks is full.
+pub struct CompressingWriter {
+    block: Vec<u8>,
+    sender: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl CompressingWriter {
+    pub fn new<W: Write + Send + 'static>(writer: W, compression: Compression) -> Self {
+        let (sender, receiver) = sync_channel(BLOCKS_QUEUED);
+        let thread = std::thread::Builder::new()
+            .name("tectonic-compress".to_string())
+            .spawn(move || compress_blocks(writer, compression, receiver))
+            .expect("spawning the compression thread to succeed");
+        return Self {
+            block: Vec::with_capacity(BLOCK_BYTES),
+            sender: Some(sender),
+            thread: Some(thread),
+        };
+    }
+
+    /// Hands the current block to the compression thread.
+    fn send_block(&mut self) -> std::io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = std::mem::replace(&mut self.block, Vec::with_capacity(BLOCK_BYTES));
+        let sent = self
+            .sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(block).is_ok());
+        if !sent {
+            // The thread only hangs up when it failed, and its error is reported by `finish`.
+            return Err(std::io::Error::other("The compression thread stopped"));
+        }
+        return Ok(());
+    }
+
+    /// Compresses the rest of the output and waits for the compression thread to finish.
+    pub fn finish(mut self) -> Result<()> {
+        let sent = self.send_block();
+        return self.join().and(sent.map_err(Into::into));
+    }
+
+    fn join(&mut self) -> Result<()> {
+        drop(self.sender.take());
+        return match self.thread.take() {
+            Some(thread) => match thread.join() {
+                Ok(result) => result,
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            None => Ok(()),
+        };
+    }
+}
+
+impl Write for CompressingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(BLOCK_BYTES - self.block.len());
+        self.block.extend_from_slice(&buf[..len]);
+        if self.block.len() == BLOCK_BYTES {
+            self.send_block()?;
+        }
+        return Ok(len);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.send_block();
+    }
+}
+
+impl Drop for CompressingWriter {
+    fn drop(&mut self) {
+        // Errors were already reported by `finish`, or are lost with the unfinished output.
+        let _ = self.join();
+    }
+}
+
+/// A workload being written to a file or stdout, compressed or not.
+pub enum WorkloadWriter {
+    Plain(BufWriter<Box<dyn Write + Send>>),
+    Compressed(CompressingWriter),
+}
+
+impl WorkloadWriter {
+    /// Creates the workload file at `path`.
+    pub fn create(path: &Path, compression: Compression) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+        return Ok(Self::new(Box::new(file), compression));
+    }
+
+    /// Writes the workload to stdout.
+    pub fn stdout(compression: Compression) -> Self {
+        return Self::new(Box::new(std::io::stdout()), compression);
+    }
+
+    fn new(writer: Box<dyn Write + Send>, compression: Compression) -> Self {
+        let writer = BufWriter::with_capacity(1024 * 1024, writer);
+        return match compression {
+            Compression::None => Self::Plain(writer),
+            compression => Self::Compressed(CompressingWriter::new(writer, compression)),
+        };
+    }
+
+    /// Writes out everything that was written, which must be called before the writer is dropped.
+    pub fn finish(self) -> Result<()> {
+        return match self {
+            Self::Plain(mut writer) => writer.flush().context("Flushing workload"),
+            Self::Compressed(writer) => writer.finish(),
+        };
+    }
+}
+
+impl Write for WorkloadWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        return match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Compressed(writer) => writer.write(buf),
+        };
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        return match self {
+            Self::Plain(writer) => writer.write_all(buf),
+            Self::Compressed(writer) => writer.write_all(buf),
+        };
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Compressed(writer) => writer.flush(),
+        };
+    }
+}
+
+/// Reads a workload from `reader`, decompressing it if it starts with the magic number of one of
+/// the compressions.
+pub fn decompress(reader: impl Read + Send + 'static) -> Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::with_capacity(1024 * 1024, reader);
+    let compression = Compression::from_header(reader.fill_buf().context("Reading workload")?);
+    return Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Zstd => Box::new(BufReader::new(
+            zstd::Decoder::with_buffer(reader).context("Creating zstd decoder")?,
+        )),
+        Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Compression::Lz4 => Box::new(BufReader::new(lz4_flex::frame::FrameDecoder::new(reader))),
+    });
+}
+
+/// Opens the workload file at `path`, decompressing it whatever its extension.
+pub fn open_workload(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    return decompress(file);
+}
@@ -3,18 +3,34 @@
 
 use rand::Rng;
 
-use crate::spec::Distribution;
+use crate::spec::{Membership, Selection, SelectionOrder};
 use bloom::{ASMS, BloomFilter};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
 use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
 use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 // pub type Key = Box<[u8]>;
 pub type Key = Rc<[u8]>;
 // TODO: Fix lifetime issue by maybe keeping the vec separate from the keysets so the lifetimes are
 // tied to it?
 
+/// A 64 bit hash of a key, for sets of keys that are too large to hold the keys themselves. The
+/// hash is the same on every run.
+pub(crate) fn key_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    return hasher.finish();
+}
+
 // Modified from https://github.com/servo/rust-fnv/blob/main/lib.rs#L146-L157 (MIT)
 const INITIAL_STATE: u64 = 0xcbf2_9ce4_8422_2325;
 const PRIME: u64 = 0x0100_0000_01b3;
@@ -56,6 +72,16 @@ fn unbiased_index(idx: usize, len: usize) -> usize {
     return (fnv_hash(idx as u64) as usize) % len;
 }
 
+/// Approximate heap memory of reference counted keys, including the reference counts.
+fn rc_keys_memory<'a>(keys: impl Iterator<Item = &'a Key>) -> usize {
+    return keys.map(|key| key.len() + 2 * size_of::<usize>()).sum();
+}
+
+/// Approximate heap memory of a hash table with the given capacity, including its control bytes.
+fn hash_table_memory<T>(capacity: usize) -> usize {
+    return capacity * (size_of::<T>() + 1);
+}
+
 pub trait KeySet {
     fn new(capacity: usize) -> Self;
 
@@ -63,15 +89,43 @@ pub trait KeySet {
 
     fn is_empty(&self) -> bool;
 
-    fn push(&mut self, key: Key);
+    /// Adds `key` to the set. Returns false, leaving the set unchanged, when `key` is already in
+    /// the set.
+    ///
+    /// `VecKeySet` and `VecBloomFilterKeySet` cannot tell cheaply, so they always add `key` and
+    /// return true. They are only used when every pushed key is fresh, i.e. every insert is
+    /// `unique`.
+    fn push(&mut self, key: Key) -> bool;
 
     fn remove(&mut self, idx: usize) -> Key;
 
-    fn remove_random(&mut self, rng: &mut impl Rng, distribution: &Distribution) -> Key {
-        let x = distribution.evaluate(rng).clamp(0., 1. - f64::EPSILON);
-        let idx = (x * self.len() as f64) as usize;
-        let idx_hashed = unbiased_index(idx, self.len());
-        return self.remove(idx_hashed);
+    /// Picks an index in `[0, len)` by sampling `selection` and applying it in the given `order`.
+    fn select(
+        &mut self,
+        rng: &mut impl Rng,
+        selection: &Selection,
+        order: SelectionOrder,
+        len: usize,
+    ) -> usize {
+        let idx = selection.sample_position(rng, len);
+        return match order {
+            SelectionOrder::Hashed => unbiased_index(idx, max(1, len)),
+            SelectionOrder::Insertion => idx,
+            SelectionOrder::Sorted => {
+                self.sort();
+                idx
+            }
+        };
+    }
+
+    fn remove_random(
+        &mut self,
+        rng: &mut impl Rng,
+        selection: &Selection,
+        order: SelectionOrder,
+    ) -> Key {
+        let idx = self.select(rng, selection, order, self.len());
+        return self.remove(idx);
     }
 
     fn remove_range(&mut self, idx_range: Range<usize>) -> (Key, Key);
@@ -79,55 +133,84 @@ pub trait KeySet {
         &mut self,
         selectivity: f64,
         rng: &mut impl Rng,
-        distribution: &Distribution,
+        selection: &Selection,
+        order: SelectionOrder,
     ) -> (Key, Key) {
-        let num_keys = self.len();
-        let range_len = (selectivity * (num_keys as f64)).floor() as usize;
-        let valid_len = num_keys - range_len;
-
-        let x = distribution.evaluate(rng).clamp(0., 1. - f64::EPSILON);
-        let start_idx = (x * valid_len as f64) as usize;
-        let end_idx = start_idx + range_len;
-
-        return self.remove_range(start_idx..end_idx);
+        let idx_range = self.select_range(selectivity, rng, selection, order);
+        return self.remove_range(idx_range);
     }
 
-    fn get(&self, idx: usize) -> &Key;
+    /// The key at `idx`. It is borrowed unless the set keeps it outside of memory.
+    fn get(&self, idx: usize) -> Cow<'_, [u8]>;
 
-    fn get_random(&self, rng: &mut impl Rng, distribution: &Distribution) -> &Key {
-        let x = distribution.evaluate(rng).clamp(0., 1. - f64::EPSILON);
-        let idx = (x * self.len() as f64) as usize;
-        let idx_hashed = unbiased_index(idx, self.len());
-        return self.get(idx_hashed);
+    fn get_random(
+        &mut self,
+        rng: &mut impl Rng,
+        selection: &Selection,
+        order: SelectionOrder,
+    ) -> Cow<'_, [u8]> {
+        let idx = self.select(rng, selection, order, self.len());
+        return self.get(idx);
     }
 
     fn get_range_random(
         &mut self,
         selectivity: f64,
         rng: &mut impl Rng,
-        distribution: &Distribution,
-    ) -> (&Key, &Key) {
+        selection: &Selection,
+        order: SelectionOrder,
+    ) -> (Cow<'_, [u8]>, Cow<'_, [u8]>) {
+        let idx_range = self.select_range(selectivity, rng, selection, order);
+        let key1 = self.get(idx_range.start);
+        let key2 = self.get(idx_range.end);
+        return (key1, key2);
+    }
+
+    /// Picks a range of indexes that holds `selectivity` of the keys, by selecting its start.
+    fn select_range(
+        &mut self,
+        selectivity: f64,
+        rng: &mut impl Rng,
+        selection: &Selection,
+        order: SelectionOrder,
+    ) -> Range<usize> {
         let num_keys = self.len();
         let range_len = (selectivity * (num_keys as f64)).floor() as usize;
         let valid_len = num_keys - range_len;
 
-        let x = distribution.evaluate(rng).clamp(0., 1. - f64::EPSILON);
-        let start_idx = (x * valid_len as f64) as usize;
-        let end_idx = start_idx + range_len;
-
-        let key1 = self.get(start_idx);
-        let key2 = self.get(end_idx);
-        return (key1, key2);
+        let start_idx = self.select(rng, selection, order, valid_len);
+        return start_idx..start_idx + range_len;
     }
 
     fn contains(&self, key: &Key) -> bool;
 
+    /// How `contains` checks membership.
+    fn membership(&self) -> Membership {
+        return Membership::Exact;
+    }
+
+    /// Iterates over the keys in index order.
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>>;
+
+    /// Approximate heap memory used by the set, in bytes.
+    fn memory_usage(&self) -> usize;
+
     fn sort(
         &mut self,
         // sort_by: SortBy
     );
+
+    /// Gives back memory that is not needed while the set is only read, such as caches.
+    fn release_memory(&mut self) {}
+
+    /// Takes the first I/O error the set ran into, for sets that keep keys on disk.
+    fn take_error(&self) -> Option<io::Error> {
+        return None;
+    }
 }
 
+/// Keys in a vector, for workloads that never check whether a key is valid. `contains` is a linear
+/// scan, and `push` keeps duplicates.
 pub struct VecKeySet {
     keys: Vec<Key>,
     sorted: bool,
@@ -149,17 +232,19 @@ impl KeySet for VecKeySet {
         return self.keys.is_empty();
     }
 
-    fn push(&mut self, key: Key) {
+    fn push(&mut self, key: Key) -> bool {
         if self.sorted && self.keys.last().is_some_and(|last_key| last_key > &key) {
             self.sorted = false;
         }
         self.keys.push(key);
+        return true;
     }
 
     fn remove(&mut self, idx: usize) -> Key {
-        let len = self.keys.len();
-        self.keys.swap(idx, len - 1);
-        return self.keys.remove(idx);
+        if idx + 1 < self.keys.len() {
+            self.sorted = false;
+        }
+        return self.keys.swap_remove(idx);
     }
 
     fn remove_range(&mut self, idx_range: Range<usize>) -> (Key, Key) {
@@ -172,14 +257,22 @@ impl KeySet for VecKeySet {
         }
     }
 
-    fn get(&self, idx: usize) -> &Key {
-        return &self.keys[idx];
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(&self.keys[idx]);
     }
 
     fn contains(&self, key: &Key) -> bool {
         return self.keys.contains(key);
     }
 
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self.keys.iter().map(|key| Cow::Borrowed(key.as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.keys.capacity() * size_of::<Key>() + rc_keys_memory(self.keys.iter());
+    }
+
     fn sort(&mut self) {
         if !self.sorted {
             self.keys.sort();
@@ -239,7 +332,10 @@ impl KeySet for VecOptionKeySet {
     }
 
     // TODO: maybe this can be improved to binary search and then "fill a hole"
-    fn push(&mut self, key: Key) {
+    fn push(&mut self, key: Key) -> bool {
+        if !self.set.insert(key.clone()) {
+            return false;
+        }
         if self.sorted
             && self
                 .keys
@@ -248,8 +344,8 @@ impl KeySet for VecOptionKeySet {
         {
             self.sorted = false;
         }
-        self.set.insert(key.clone());
         self.keys.push(Some(key));
+        return true;
     }
 
     fn remove(&mut self, mut idx: usize) -> Key {
@@ -305,11 +401,11 @@ impl KeySet for VecOptionKeySet {
     //     return (key1, key2);
     // }
 
-    fn get(&self, mut idx: usize) -> &Key {
+    fn get(&self, mut idx: usize) -> Cow<'_, [u8]> {
         for _ in 0..self.keys.len() {
             match self.maybe_get(idx) {
                 Some(key) => {
-                    return key;
+                    return Cow::Borrowed(key);
                 }
                 None => {
                     idx = (idx + 1) % self.keys.len();
@@ -325,6 +421,20 @@ impl KeySet for VecOptionKeySet {
         // return self.keys.iter().any(|k| k.as_ref() == Some(key));
     }
 
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self
+            .keys
+            .iter()
+            .flatten()
+            .map(|key| Cow::Borrowed(key.as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.keys.capacity() * size_of::<Option<Key>>()
+            + hash_table_memory::<Key>(self.set.capacity())
+            + rc_keys_memory(self.keys.iter().flatten());
+    }
+
     fn sort(&mut self) {
         if !self.sorted {
             self.maybe_flatten_in_place();
@@ -334,6 +444,196 @@ impl KeySet for VecOptionKeySet {
     }
 }
 
+/// The most unsorted keys that `SlotMapKeySet::sort` inserts one by one instead of merging.
+const SLOT_MAP_KEY_SET_MAX_INSERTION_SORT: usize = 16;
+
+/// A handle to a slot of `SlotMapKeySet`. It is only valid while the generation matches the slot's.
+#[derive(Clone, Copy, Debug)]
+struct SlotId {
+    index: u32,
+    generation: u32,
+}
+
+struct Slot {
+    key: Option<Key>,
+    generation: u32,
+}
+
+/// Keeps keys in stable slots that are reused through a free list, and orders them with a dense
+/// array of 8 byte handles. Random access and removal are O(1), and reordering never moves keys.
+///
+/// Removing a key moves the last handle into its place, like `Vec::swap_remove`. Once the set has
+/// been sorted, removing a key from the sorted prefix shifts the handles after it instead, a
+/// memmove of 8 byte handles that keeps the prefix sorted. Sorting only sorts the keys after the
+/// sorted prefix and merges them into it.
+pub struct SlotMapKeySet {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    /// Handles of the keys, in index order.
+    dense: Vec<SlotId>,
+    set: HashSet<Key>,
+    /// The length of the sorted prefix of `dense`.
+    sorted_len: usize,
+    /// Whether `sort` has been called, i.e. whether keys are selected in sorted order.
+    keep_sorted: bool,
+}
+
+impl SlotMapKeySet {
+    fn slot_key(&self, id: SlotId) -> &Key {
+        return slot_key(&self.slots, id);
+    }
+
+    fn insert_slot(&mut self, key: Key) -> SlotId {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.key = Some(key);
+            return SlotId {
+                index,
+                generation: slot.generation,
+            };
+        }
+        let index = u32::try_from(self.slots.len()).expect("to have at most u32::MAX slots");
+        self.slots.push(Slot {
+            key: Some(key),
+            generation: 0,
+        });
+        return SlotId {
+            index,
+            generation: 0,
+        };
+    }
+
+    fn remove_slot(&mut self, id: SlotId) -> Key {
+        let slot = &mut self.slots[id.index as usize];
+        debug_assert_eq!(slot.generation, id.generation, "stale slot handle");
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        return slot.key.take().expect("slot to be occupied");
+    }
+}
+
+fn slot_key(slots: &[Slot], id: SlotId) -> &Key {
+    let slot = &slots[id.index as usize];
+    debug_assert_eq!(slot.generation, id.generation, "stale slot handle");
+    return slot.key.as_ref().expect("slot to be occupied");
+}
+
+impl KeySet for SlotMapKeySet {
+    fn new(capacity: usize) -> Self {
+        return Self {
+            slots: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            dense: Vec::with_capacity(capacity),
+            set: HashSet::with_capacity(capacity),
+            sorted_len: 0,
+            keep_sorted: false,
+        };
+    }
+
+    fn len(&self) -> usize {
+        return self.dense.len();
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.dense.is_empty();
+    }
+
+    fn push(&mut self, key: Key) -> bool {
+        if !self.set.insert(key.clone()) {
+            return false;
+        }
+        let extends_sorted = self.sorted_len == self.dense.len()
+            && self
+                .dense
+                .last()
+                .is_none_or(|&last| self.slot_key(last) < &key);
+        let id = self.insert_slot(key);
+        self.dense.push(id);
+        if extends_sorted {
+            self.sorted_len += 1;
+        }
+        return true;
+    }
+
+    fn remove(&mut self, idx: usize) -> Key {
+        let id = if self.keep_sorted && idx < self.sorted_len {
+            self.sorted_len -= 1;
+            self.dense.remove(idx)
+        } else {
+            // Everything before `idx` stays sorted. If `idx` was the last key, that is all of them.
+            self.sorted_len = self.sorted_len.min(idx);
+            self.dense.swap_remove(idx)
+        };
+        let key = self.remove_slot(id);
+        self.set.remove(&key);
+        return key;
+    }
+
+    fn remove_range(&mut self, idx_range: Range<usize>) -> (Key, Key) {
+        let key1 = self.slot_key(self.dense[idx_range.start]).clone();
+        let key2 = self.slot_key(self.dense[idx_range.end - 1]).clone();
+        self.sorted_len = if self.sorted_len >= idx_range.end {
+            self.sorted_len - idx_range.len()
+        } else {
+            self.sorted_len.min(idx_range.start)
+        };
+        let ids: Vec<SlotId> = self.dense.drain(idx_range).collect();
+        for id in ids {
+            let key = self.remove_slot(id);
+            self.set.remove(&key);
+        }
+        return (key1, key2);
+    }
+
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(self.slot_key(self.dense[idx]));
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        return self.set.contains(key);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self
+            .dense
+            .iter()
+            .map(|&id| Cow::Borrowed(self.slot_key(id).as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.slots.capacity() * size_of::<Slot>()
+            + self.free.capacity() * size_of::<u32>()
+            + self.dense.capacity() * size_of::<SlotId>()
+            + hash_table_memory::<Key>(self.set.capacity())
+            + rc_keys_memory(self.set.iter());
+    }
+
+    fn sort(&mut self) {
+        self.keep_sorted = true;
+        let slots = &self.slots;
+        let unsorted = self.dense.len() - self.sorted_len;
+        if unsorted > 0 && unsorted <= SLOT_MAP_KEY_SET_MAX_INSERTION_SORT {
+            // A few keys were pushed since the last sort, so shifting handles is cheaper than
+            // merging.
+            for _ in 0..unsorted {
+                let id = self.dense.pop().expect("unsorted keys to remain");
+                let key = slot_key(slots, id);
+                let pos = self.dense[..self.sorted_len]
+                    .partition_point(|&other| slot_key(slots, other) < key);
+                self.dense.insert(pos, id);
+                self.sorted_len += 1;
+            }
+        } else if unsorted > 0 {
+            self.dense[self.sorted_len..]
+                .sort_unstable_by(|&a, &b| slot_key(slots, a).cmp(slot_key(slots, b)));
+            // The stable sort finds the two sorted runs and merges them in linear time.
+            self.dense
+                .sort_by(|&a, &b| slot_key(slots, a).cmp(slot_key(slots, b)));
+            self.sorted_len = self.dense.len();
+        }
+    }
+}
+
 pub struct VecHashSetKeySet {
     keys: Vec<Key>,
     key_set: HashSet<Key>,
@@ -357,12 +657,15 @@ impl KeySet for VecHashSetKeySet {
         return self.keys.is_empty();
     }
 
-    fn push(&mut self, key: Key) {
+    fn push(&mut self, key: Key) -> bool {
+        if !self.key_set.insert(key.clone()) {
+            return false;
+        }
         if self.sorted && self.keys.last().is_some_and(|last_key| last_key > &key) {
             self.sorted = false;
         }
-        self.keys.push(key.clone());
-        self.key_set.insert(key);
+        self.keys.push(key);
+        return true;
     }
 
     fn remove(&mut self, idx: usize) -> Key {
@@ -382,14 +685,24 @@ impl KeySet for VecHashSetKeySet {
         }
     }
 
-    fn get(&self, idx: usize) -> &Key {
-        return &self.keys[idx];
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(&self.keys[idx]);
     }
 
     fn contains(&self, key: &Key) -> bool {
         return self.key_set.contains(key);
     }
 
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self.keys.iter().map(|key| Cow::Borrowed(key.as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.keys.capacity() * size_of::<Key>()
+            + hash_table_memory::<Key>(self.key_set.capacity())
+            + rc_keys_memory(self.keys.iter());
+    }
+
     fn sort(&mut self) {
         if !self.sorted {
             self.keys.sort();
@@ -398,20 +711,51 @@ impl KeySet for VecHashSetKeySet {
     }
 }
 
+/// Builds hashers that are seeded with a constant, so that bloom filters give the same false
+/// positives on every run and the workload only depends on its seed.
+#[derive(Clone, Copy)]
+struct FixedHasherBuilder(u64);
+
+impl BuildHasher for FixedHasherBuilder {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.0);
+        return hasher;
+    }
+}
+
+type FixedBloomFilter = BloomFilter<FixedHasherBuilder, FixedHasherBuilder>;
+
 pub struct VecBloomFilterKeySet {
     keys: Vec<Key>,
-    bf: BloomFilter,
+    bf: FixedBloomFilter,
+    fp_rate: f64,
     sorted: bool,
 }
 
-impl KeySet for VecBloomFilterKeySet {
-    fn new(capacity: usize) -> Self {
+impl VecBloomFilterKeySet {
+    /// Creates a key set whose bloom filter has the given false positive rate.
+    pub fn with_rate(capacity: usize, fp_rate: f64) -> Self {
         return Self {
             keys: Vec::with_capacity(capacity),
-            bf: BloomFilter::with_rate(0.01, max(1, capacity) as u32),
+            bf: BloomFilter::with_rate_and_hashers(
+                fp_rate as f32,
+                max(1, capacity) as u32,
+                FixedHasherBuilder(0),
+                FixedHasherBuilder(1),
+            ),
+            fp_rate,
             sorted: true,
         };
     }
+}
+
+impl KeySet for VecBloomFilterKeySet {
+    fn new(capacity: usize) -> Self {
+        return Self::with_rate(capacity, 0.01);
+    }
 
     fn len(&self) -> usize {
         return self.keys.len();
@@ -421,12 +765,15 @@ impl KeySet for VecBloomFilterKeySet {
         return self.keys.is_empty();
     }
 
-    fn push(&mut self, key: Key) {
+    fn push(&mut self, key: Key) -> bool {
+        // The bloom filter cannot confirm a duplicate, and dropping a false positive would lose a
+        // fresh key, so every key is kept.
         if self.sorted && self.keys.last().is_some_and(|last_key| last_key > &key) {
             self.sorted = false;
         }
         self.bf.insert(&key);
         self.keys.push(key);
+        return true;
     }
 
     fn remove(&mut self, idx: usize) -> Key {
@@ -452,14 +799,30 @@ impl KeySet for VecBloomFilterKeySet {
         // }
     }
 
-    fn get(&self, idx: usize) -> &Key {
-        return &self.keys[idx];
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(&self.keys[idx]);
     }
 
     fn contains(&self, key: &Key) -> bool {
         return self.bf.contains(key);
     }
 
+    fn membership(&self) -> Membership {
+        return Membership::Bloom {
+            fp_rate: self.fp_rate,
+        };
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self.keys.iter().map(|key| Cow::Borrowed(key.as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.keys.capacity() * size_of::<Key>()
+            + self.bf.num_bits() / 8
+            + rc_keys_memory(self.keys.iter());
+    }
+
     fn sort(&mut self) {
         if !self.sorted {
             self.keys.sort();
@@ -492,14 +855,16 @@ impl KeySet for VecHashMapIndexKeySet {
         return self.keys.is_empty();
     }
 
-    fn push(&mut self, key: Key) {
-        if !self.key_to_index.contains_key(&key) {
-            if self.sorted && self.keys.last().is_some_and(|last_key| last_key > &key) {
-                self.sorted = false;
-            }
-            self.key_to_index.insert(key.clone(), self.keys.len());
-            self.keys.push(key);
+    fn push(&mut self, key: Key) -> bool {
+        if self.key_to_index.contains_key(&key) {
+            return false;
+        }
+        if self.sorted && self.keys.last().is_some_and(|last_key| last_key > &key) {
+            self.sorted = false;
         }
+        self.key_to_index.insert(key.clone(), self.keys.len());
+        self.keys.push(key);
+        return true;
     }
 
     fn remove(&mut self, idx: usize) -> Key {
@@ -533,14 +898,24 @@ impl KeySet for VecHashMapIndexKeySet {
         }
     }
 
-    fn get(&self, idx: usize) -> &Key {
-        return &self.keys[idx];
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(&self.keys[idx]);
     }
 
     fn contains(&self, key: &Key) -> bool {
         return self.key_to_index.contains_key(key);
     }
 
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self.keys.iter().map(|key| Cow::Borrowed(key.as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.keys.capacity() * size_of::<Key>()
+            + hash_table_memory::<(Key, usize)>(self.key_to_index.capacity())
+            + rc_keys_memory(self.keys.iter());
+    }
+
     fn sort(&mut self) {
         if !self.sorted {
             self.keys.sort();
@@ -574,8 +949,8 @@ impl KeySet for BTreeSetKeySet {
         return self.keys.is_empty();
     }
 
-    fn push(&mut self, key: Key) {
-        self.keys.insert(key);
+    fn push(&mut self, key: Key) -> bool {
+        return self.keys.insert(key);
     }
 
     fn remove(&mut self, idx: usize) -> Key {
@@ -607,8 +982,8 @@ impl KeySet for BTreeSetKeySet {
         }
     }
 
-    fn get(&self, idx: usize) -> &Key {
-        return self.keys.iter().nth(idx).expect("idx to be in range");
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(self.keys.iter().nth(idx).expect("idx to be in range"));
     }
 
     // fn get_random(&self, _rng: &mut impl Rng) -> &Key {
@@ -620,6 +995,15 @@ impl KeySet for BTreeSetKeySet {
         return self.keys.contains(key);
     }
 
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self.keys.iter().map(|key| Cow::Borrowed(key.as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        // B-tree nodes are at least half full.
+        return 2 * self.keys.len() * size_of::<Key>() + rc_keys_memory(self.keys.iter());
+    }
+
     fn sort(&mut self) {
         /* no op -- already sorted */
         // match sort_by {
@@ -628,3 +1012,1706 @@ impl KeySet for BTreeSetKeySet {
         // }
     }
 }
+
+/// The most entries, keys or children, that a node of `OrderStatisticTreeKeySet` holds before it
+/// is split in two.
+const ORDER_STATISTIC_NODE_MAX: usize = 64;
+/// The fewest entries that a node of `OrderStatisticTreeKeySet` holds before it is merged with a
+/// sibling.
+const ORDER_STATISTIC_NODE_MIN: usize = ORDER_STATISTIC_NODE_MAX / 4;
+
+enum OrderStatisticNode {
+    Leaf(Vec<Key>),
+    Internal(Vec<OrderStatisticChild>),
+}
+
+/// A child of an internal node, with the number of keys below it and its smallest key.
+struct OrderStatisticChild {
+    node: OrderStatisticNode,
+    len: usize,
+    min: Key,
+}
+
+impl OrderStatisticChild {
+    fn new(node: OrderStatisticNode) -> Self {
+        let len = node.len();
+        let min = node.min().clone();
+        return Self { node, len, min };
+    }
+
+    /// Removes the keys in `idx_range`, which must leave at least one key in the child.
+    fn remove_range(&mut self, idx_range: Range<usize>) {
+        self.len -= idx_range.len();
+        let is_min_removed = idx_range.start == 0;
+        self.node.remove_range(idx_range);
+        if is_min_removed {
+            self.min = self.node.min().clone();
+        }
+    }
+}
+
+impl OrderStatisticNode {
+    fn entries(&self) -> usize {
+        return match self {
+            Self::Leaf(keys) => keys.len(),
+            Self::Internal(children) => children.len(),
+        };
+    }
+
+    fn len(&self) -> usize {
+        return match self {
+            Self::Leaf(keys) => keys.len(),
+            Self::Internal(children) => children.iter().map(|child| child.len).sum(),
+        };
+    }
+
+    fn min(&self) -> &Key {
+        return match self {
+            Self::Leaf(keys) => &keys[0],
+            Self::Internal(children) => &children[0].min,
+        };
+    }
+
+    fn split_off_half(&mut self) -> Self {
+        return match self {
+            Self::Leaf(keys) => Self::Leaf(keys.split_off(keys.len() / 2)),
+            Self::Internal(children) => Self::Internal(children.split_off(children.len() / 2)),
+        };
+    }
+
+    fn append(&mut self, other: Self) {
+        match (self, other) {
+            (Self::Leaf(keys), Self::Leaf(mut other)) => keys.append(&mut other),
+            (Self::Internal(children), Self::Internal(mut other)) => children.append(&mut other),
+            _ => unreachable!("siblings to be at the same depth"),
+        }
+    }
+
+    /// The child holding the key with rank `idx`, and the rank within that child.
+    fn child_at(children: &[OrderStatisticChild], mut idx: usize) -> (usize, usize) {
+        for (i, child) in children.iter().enumerate() {
+            if idx < child.len {
+                return (i, idx);
+            }
+            idx -= child.len;
+        }
+        panic!("idx to be in range");
+    }
+
+    /// The child whose key range `key` falls in.
+    fn child_for(children: &[OrderStatisticChild], key: &Key) -> usize {
+        return children
+            .partition_point(|child| child.min <= *key)
+            .saturating_sub(1);
+    }
+
+    fn get(&self, idx: usize) -> &Key {
+        return match self {
+            Self::Leaf(keys) => &keys[idx],
+            Self::Internal(children) => {
+                let (i, child_idx) = Self::child_at(children, idx);
+                children[i].node.get(child_idx)
+            }
+        };
+    }
+
+    /// The number of keys in the node that are smaller than `key`.
+    fn rank(&self, key: &[u8]) -> usize {
+        return match self {
+            Self::Leaf(keys) => keys.partition_point(|k| k.as_ref() < key),
+            Self::Internal(children) => {
+                let i = children
+                    .partition_point(|child| child.min.as_ref() <= key)
+                    .saturating_sub(1);
+                let before: usize = children[..i].iter().map(|child| child.len).sum();
+                before + children[i].node.rank(key)
+            }
+        };
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        return match self {
+            Self::Leaf(keys) => keys.binary_search(key).is_ok(),
+            Self::Internal(children) => children[Self::child_for(children, key)].node.contains(key),
+        };
+    }
+
+    /// Inserts `key` unless it is already in the node. Returns whether it was inserted, and the
+    /// right half of the node if it had to be split.
+    fn insert(&mut self, key: Key) -> (bool, Option<Self>) {
+        let inserted = match self {
+            Self::Leaf(keys) => match keys.binary_search(&key) {
+                Ok(_) => false,
+                Err(pos) => {
+                    keys.insert(pos, key);
+                    true
+                }
+            },
+            Self::Internal(children) => {
+                let i = Self::child_for(children, &key);
+                let child = &mut children[i];
+                let is_min = key < child.min;
+                let (inserted, split) = child.node.insert(key);
+                if inserted {
+                    child.len += 1;
+                    if is_min {
+                        child.min = child.node.min().clone();
+                    }
+                }
+                if let Some(sibling) = split {
+                    let sibling = OrderStatisticChild::new(sibling);
+                    children[i].len -= sibling.len;
+                    children.insert(i + 1, sibling);
+                }
+                inserted
+            }
+        };
+        let split = (self.entries() > ORDER_STATISTIC_NODE_MAX).then(|| self.split_off_half());
+        return (inserted, split);
+    }
+
+    fn remove(&mut self, idx: usize) -> Key {
+        return match self {
+            Self::Leaf(keys) => keys.remove(idx),
+            Self::Internal(children) => {
+                let (i, child_idx) = Self::child_at(children, idx);
+                let child = &mut children[i];
+                let key = child.node.remove(child_idx);
+                child.len -= 1;
+                if child_idx == 0 && child.len > 0 {
+                    child.min = child.node.min().clone();
+                }
+                Self::rebalance(children, i);
+                key
+            }
+        };
+    }
+
+    /// Removes the keys in `idx_range`, which must not be empty.
+    fn remove_range(&mut self, idx_range: Range<usize>) {
+        let children = match self {
+            Self::Leaf(keys) => {
+                keys.drain(idx_range);
+                return;
+            }
+            Self::Internal(children) => children,
+        };
+        let (first, first_start) = Self::child_at(children, idx_range.start);
+        let (last, last_end) = Self::child_at(children, idx_range.end - 1);
+        let last_end = last_end + 1;
+        let keep_first = first_start > 0;
+        let keep_last = last_end < children[last].len;
+
+        if first == last {
+            if keep_first || keep_last {
+                children[first].remove_range(first_start..last_end);
+                Self::rebalance(children, first);
+            } else {
+                children.remove(first);
+            }
+            return;
+        }
+
+        // Trims the first and the last child, and drops every child that is fully covered.
+        if keep_last {
+            children[last].remove_range(0..last_end);
+        }
+        if keep_first {
+            let first_len = children[first].len;
+            children[first].remove_range(first_start..first_len);
+        }
+        let drain_start = if keep_first { first + 1 } else { first };
+        let drain_end = if keep_last { last } else { last + 1 };
+        children.drain(drain_start..drain_end);
+        if keep_last {
+            Self::rebalance(children, drain_start);
+        }
+        if keep_first {
+            Self::rebalance(children, first);
+        }
+    }
+
+    /// Merges the child at `i` with a sibling if it has too few entries, splitting the merged
+    /// node again if it has too many.
+    fn rebalance(children: &mut Vec<OrderStatisticChild>, i: usize) {
+        if children[i].node.entries() >= ORDER_STATISTIC_NODE_MIN || children.len() < 2 {
+            return;
+        }
+        let left = if i + 1 < children.len() { i } else { i - 1 };
+        let right = children.remove(left + 1);
+        let child = &mut children[left];
+        child.node.append(right.node);
+        child.len += right.len;
+        if child.node.entries() > ORDER_STATISTIC_NODE_MAX {
+            let sibling = OrderStatisticChild::new(child.node.split_off_half());
+            child.len -= sibling.len;
+            children.insert(left + 1, sibling);
+        }
+        let child = &mut children[left];
+        if child.len > 0 {
+            child.min = child.node.min().clone();
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Key> + '_> {
+        return match self {
+            Self::Leaf(keys) => Box::new(keys.iter()),
+            Self::Internal(children) => {
+                Box::new(children.iter().flat_map(|child| child.node.iter()))
+            }
+        };
+    }
+
+    fn memory_usage(&self) -> usize {
+        return match self {
+            Self::Leaf(keys) => keys.capacity() * size_of::<Key>(),
+            Self::Internal(children) => {
+                children.capacity() * size_of::<OrderStatisticChild>()
+                    + children
+                        .iter()
+                        .map(|child| child.node.memory_usage())
+                        .sum::<usize>()
+            }
+        };
+    }
+}
+
+/// A B-tree whose internal nodes count the keys below each child, so that finding, removing, or
+/// removing a range of keys by index takes O(log n). Keys are always in sorted order, so `sort` is
+/// a no-op, which suits range operations with start and end keys when inserts interleave with
+/// them.
+pub struct OrderStatisticTreeKeySet {
+    root: OrderStatisticNode,
+    len: usize,
+}
+
+impl OrderStatisticTreeKeySet {
+    /// Replaces an internal root that has a single child, or none, with that child.
+    fn collapse_root(&mut self) {
+        loop {
+            let OrderStatisticNode::Internal(children) = &mut self.root else {
+                return;
+            };
+            self.root = match children.len() {
+                0 => OrderStatisticNode::Leaf(Vec::new()),
+                1 => children.pop().expect("root to have a child").node,
+                _ => return,
+            };
+        }
+    }
+
+    /// The number of keys that are smaller than `key`, which is the index of `key` if it is in the
+    /// set.
+    pub fn rank(&self, key: &[u8]) -> usize {
+        return self.root.rank(key);
+    }
+}
+
+impl KeySet for OrderStatisticTreeKeySet {
+    fn new(_capacity: usize) -> Self {
+        return Self {
+            root: OrderStatisticNode::Leaf(Vec::new()),
+            len: 0,
+        };
+    }
+
+    fn len(&self) -> usize {
+        return self.len;
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    fn push(&mut self, key: Key) -> bool {
+        let (inserted, split) = self.root.insert(key);
+        if let Some(sibling) = split {
+            let root = std::mem::replace(&mut self.root, OrderStatisticNode::Leaf(Vec::new()));
+            self.root = OrderStatisticNode::Internal(vec![
+                OrderStatisticChild::new(root),
+                OrderStatisticChild::new(sibling),
+            ]);
+        }
+        if inserted {
+            self.len += 1;
+        }
+        return inserted;
+    }
+
+    fn remove(&mut self, idx: usize) -> Key {
+        let key = self.root.remove(idx);
+        self.len -= 1;
+        self.collapse_root();
+        return key;
+    }
+
+    fn remove_range(&mut self, idx_range: Range<usize>) -> (Key, Key) {
+        let key1 = self.root.get(idx_range.start).clone();
+        if idx_range.is_empty() {
+            return (key1.clone(), key1);
+        }
+        let key2 = self.root.get(idx_range.end - 1).clone();
+        self.len -= idx_range.len();
+        self.root.remove_range(idx_range);
+        self.collapse_root();
+        return (key1, key2);
+    }
+
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(self.root.get(idx));
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        return !self.is_empty() && self.root.contains(key);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self.root.iter().map(|key| Cow::Borrowed(key.as_ref()));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.root.memory_usage() + rc_keys_memory(self.root.iter());
+    }
+
+    fn sort(&mut self) {
+        /* no op -- already sorted */
+    }
+}
+
+/// FNV-1a hash of a byte string.
+#[inline]
+fn fnv_hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash = INITIAL_STATE;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    return hash;
+}
+
+/// The number of bits of an `ArenaSpan` used for the offset. The rest store the length.
+const ARENA_OFFSET_BITS: u32 = 40;
+
+/// The location of a key in the arena, packed into 8 bytes.
+#[derive(Clone, Copy)]
+struct ArenaSpan(u64);
+
+impl ArenaSpan {
+    fn new(offset: usize, len: usize) -> Self {
+        assert!(
+            offset < 1 << ARENA_OFFSET_BITS && len < 1 << (u64::BITS - ARENA_OFFSET_BITS),
+            "key arena is limited to 1 TiB and keys to 16 MiB"
+        );
+        return Self(offset as u64 | (len as u64) << ARENA_OFFSET_BITS);
+    }
+
+    fn range(self) -> Range<usize> {
+        let offset = (self.0 & ((1 << ARENA_OFFSET_BITS) - 1)) as usize;
+        let len = (self.0 >> ARENA_OFFSET_BITS) as usize;
+        return offset..offset + len;
+    }
+}
+
+/// The smallest number of dead bytes in the arena that triggers a compaction.
+const ARENA_KEY_SET_MIN_COMPACTION: usize = 1 << 20;
+
+/// Stores keys back to back in a byte arena, so a key costs its bytes plus 8 bytes for its span and
+/// 11 to 22 bytes of hash index, whose 8 byte slots are a power of two at most 3/4 full, without an
+/// allocation per key.
+///
+/// Removing a key moves the last key into its place, like `Vec::swap_remove`.
+pub struct ArenaKeySet {
+    /// Key bytes, including those of removed keys until the arena is compacted.
+    arena: Vec<u8>,
+    spans: Vec<ArenaSpan>,
+    /// Open addressing hash table with linear probing. A slot holds the upper 32 bits of the key's
+    /// hash and the key's index in `spans` plus one, with zero marking an empty slot.
+    index: Vec<u64>,
+    dead_bytes: usize,
+    sorted: bool,
+}
+
+impl ArenaKeySet {
+    fn key(&self, idx: usize) -> &[u8] {
+        return &self.arena[self.spans[idx].range()];
+    }
+
+    fn index_capacity(len: usize) -> usize {
+        // Keep the load factor at most 3/4.
+        return max(8, (len * 4 / 3 + 1).next_power_of_two());
+    }
+
+    /// The largest capacity whose spans and index fit in `bytes`, at least one.
+    fn capacity_for_memory(bytes: usize) -> usize {
+        let fits = |capacity: usize| {
+            capacity * size_of::<ArenaSpan>() + Self::index_capacity(capacity) * size_of::<u64>()
+                <= bytes
+        };
+        let (mut low, mut high) = (1, max(1, bytes / size_of::<ArenaSpan>()));
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if fits(mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        return low;
+    }
+
+    fn slot_entry(hash: u64, idx: usize) -> u64 {
+        return (hash & !u64::from(u32::MAX)) | (idx as u64 + 1);
+    }
+
+    fn home_slot(&self, entry_or_hash: u64) -> usize {
+        return (entry_or_hash >> 32) as usize & (self.index.len() - 1);
+    }
+
+    /// Finds the slot of `key`, or the empty slot where it would go.
+    fn find(&self, key: &[u8], hash: u64) -> Result<usize, usize> {
+        let mask = self.index.len() - 1;
+        let mut slot = self.home_slot(hash);
+        loop {
+            let entry = self.index[slot];
+            if entry == 0 {
+                return Err(slot);
+            }
+            if entry >> 32 == hash >> 32 && self.key(entry as u32 as usize - 1) == key {
+                return Ok(slot);
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    fn rebuild_index(&mut self, capacity: usize) {
+        self.index = vec![0; capacity];
+        for idx in 0..self.spans.len() {
+            let hash = fnv_hash_bytes(self.key(idx));
+            let slot = self
+                .find(self.key(idx), hash)
+                .expect_err("keys to be unique");
+            self.index[slot] = Self::slot_entry(hash, idx);
+        }
+    }
+
+    /// Empties `slot`, shifting later entries of the probe sequence back so that lookups never
+    /// stop early.
+    fn remove_slot(&mut self, slot: usize) {
+        let mask = self.index.len() - 1;
+        let mut hole = slot;
+        let mut next = (hole + 1) & mask;
+        loop {
+            let entry = self.index[next];
+            if entry == 0 {
+                break;
+            }
+            // The entry can fill the hole if the hole lies between its home slot and its slot.
+            let home = self.home_slot(entry);
+            if next.wrapping_sub(home) & mask >= next.wrapping_sub(hole) & mask {
+                self.index[hole] = entry;
+                hole = next;
+            }
+            next = (next + 1) & mask;
+        }
+        self.index[hole] = 0;
+    }
+
+    fn slot_of(&self, idx: usize) -> usize {
+        let key = self.key(idx);
+        return self
+            .find(key, fnv_hash_bytes(key))
+            .expect("key to be indexed");
+    }
+
+    fn maybe_compact(&mut self) {
+        if self.dead_bytes < ARENA_KEY_SET_MIN_COMPACTION || self.dead_bytes < self.arena.len() / 2
+        {
+            return;
+        }
+        let mut arena = Vec::with_capacity(self.arena.len() - self.dead_bytes);
+        for span in &mut self.spans {
+            let range = span.range();
+            *span = ArenaSpan::new(arena.len(), range.len());
+            arena.extend_from_slice(&self.arena[range]);
+        }
+        self.arena = arena;
+        self.dead_bytes = 0;
+    }
+}
+
+impl KeySet for ArenaKeySet {
+    fn new(capacity: usize) -> Self {
+        return Self {
+            arena: Vec::new(),
+            spans: Vec::with_capacity(capacity),
+            index: vec![0; Self::index_capacity(capacity)],
+            dead_bytes: 0,
+            sorted: true,
+        };
+    }
+
+    fn len(&self) -> usize {
+        return self.spans.len();
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.spans.is_empty();
+    }
+
+    fn push(&mut self, key: Key) -> bool {
+        assert!(self.spans.len() < u32::MAX as usize, "too many keys");
+        if Self::index_capacity(self.spans.len() + 1) > self.index.len() {
+            self.rebuild_index(self.index.len() * 2);
+        }
+        let hash = fnv_hash_bytes(&key);
+        let Err(slot) = self.find(&key, hash) else {
+            return false;
+        };
+        if self.sorted
+            && self
+                .spans
+                .last()
+                .is_some_and(|_| self.key(self.len() - 1) > &key)
+        {
+            self.sorted = false;
+        }
+        self.index[slot] = Self::slot_entry(hash, self.spans.len());
+        self.spans.push(ArenaSpan::new(self.arena.len(), key.len()));
+        self.arena.extend_from_slice(&key);
+        return true;
+    }
+
+    fn remove(&mut self, idx: usize) -> Key {
+        let key = Key::from(self.key(idx));
+        let slot = self.slot_of(idx);
+        self.remove_slot(slot);
+
+        let last = self.spans.len() - 1;
+        if idx != last {
+            let slot = self.slot_of(last);
+            self.index[slot] = Self::slot_entry(self.index[slot], idx);
+            self.sorted = false;
+        }
+        self.spans.swap_remove(idx);
+        self.dead_bytes += key.len();
+        self.maybe_compact();
+        return key;
+    }
+
+    fn remove_range(&mut self, idx_range: Range<usize>) -> (Key, Key) {
+        let key1 = Key::from(self.key(idx_range.start));
+        let key2 = Key::from(self.key(idx_range.end - 1));
+        // Removing from the back only moves keys from after the range into it.
+        for idx in idx_range.rev() {
+            self.remove(idx);
+        }
+        return (key1, key2);
+    }
+
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(self.key(idx));
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        return self.find(key, fnv_hash_bytes(key)).is_ok();
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        return self
+            .spans
+            .iter()
+            .map(|span| Cow::Borrowed(&self.arena[span.range()]));
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.arena.capacity()
+            + self.spans.capacity() * size_of::<ArenaSpan>()
+            + self.index.capacity() * size_of::<u64>();
+    }
+
+    fn sort(&mut self) {
+        if !self.sorted {
+            let arena = &self.arena;
+            self.spans
+                .sort_unstable_by(|a, b| arena[a.range()].cmp(&arena[b.range()]));
+            self.rebuild_index(self.index.len());
+            self.sorted = true;
+        }
+    }
+}
+
+/// Fenwick tree over a list of counts, used to find the entry that holds the element of a given
+/// rank.
+struct FenwickTree {
+    tree: Vec<usize>,
+}
+
+impl FenwickTree {
+    fn from_counts(counts: impl Iterator<Item = usize>) -> Self {
+        let mut tree: Vec<usize> = counts.collect();
+        for i in 1..=tree.len() {
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= tree.len() {
+                tree[parent - 1] += tree[i - 1];
+            }
+        }
+        return Self { tree };
+    }
+
+    fn add(&mut self, idx: usize, delta: isize) {
+        let mut i = idx + 1;
+        while i <= self.tree.len() {
+            self.tree[i - 1] = self.tree[i - 1].wrapping_add_signed(delta);
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// The sum of the counts before entry `end`.
+    fn prefix(&self, end: usize) -> usize {
+        let mut sum = 0;
+        let mut i = end;
+        while i > 0 {
+            sum += self.tree[i - 1];
+            i &= i - 1;
+        }
+        return sum;
+    }
+
+    /// The entry holding the element with the given rank, and the rank within that entry.
+    fn select(&self, mut rank: usize) -> (usize, usize) {
+        let mut pos = 0;
+        let mut step = match self.tree.len() {
+            0 => 0,
+            len => 1 << len.ilog2(),
+        };
+        while step > 0 {
+            let next = pos + step;
+            if next <= self.tree.len() && self.tree[next - 1] <= rank {
+                pos = next;
+                rank -= self.tree[next - 1];
+            }
+            step >>= 1;
+        }
+        return (pos, rank);
+    }
+}
+
+/// The target size of a page of a spilled run, in bytes.
+const SPILL_PAGE_BYTES: usize = 64 * 1024;
+/// The number of runs that triggers merging them into one.
+const SPILL_MAX_RUNS: usize = 4;
+/// The false positive rate of the bloom filter of each run, which decides how often a push for a
+/// fresh key reads a page of the run.
+const SPILL_BLOOM_FP_RATE: f32 = 0.01;
+
+/// A page of a spilled run, decoded.
+struct SpillPageData {
+    bytes: Vec<u8>,
+    ends: Vec<u32>,
+}
+
+impl SpillPageData {
+    fn key(&self, idx: usize) -> &[u8] {
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] };
+        return &self.bytes[start as usize..self.ends[idx] as usize];
+    }
+
+    fn len(&self) -> usize {
+        return self.ends.len();
+    }
+
+    /// Writes the number of keys, their end offsets and then their bytes. Returns the number of
+    /// bytes written.
+    fn write(&self, writer: &mut impl Write) -> io::Result<usize> {
+        writer.write_all(&(self.ends.len() as u32).to_le_bytes())?;
+        for end in &self.ends {
+            writer.write_all(&end.to_le_bytes())?;
+        }
+        writer.write_all(&self.bytes)?;
+        return Ok(4 + 4 * self.ends.len() + self.bytes.len());
+    }
+
+    fn decode(mut buf: Vec<u8>) -> Self {
+        let count = u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize;
+        let ends = buf[4..4 + 4 * count]
+            .chunks_exact(4)
+            .map(|end| u32::from_le_bytes(end.try_into().unwrap()))
+            .collect();
+        buf.drain(..4 + 4 * count);
+        return Self { bytes: buf, ends };
+    }
+
+    /// The number of keys in the page, which is sorted, that are smaller than `key`.
+    fn lower_bound(&self, key: &[u8]) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.key(mid) < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        return low;
+    }
+
+    /// The position of `key` in the page.
+    fn find(&self, key: &[u8]) -> Option<usize> {
+        let pos = self.lower_bound(key);
+        return (pos < self.len() && self.key(pos) == key).then_some(pos);
+    }
+}
+
+/// Where a page of a spilled run is on disk, and what it holds.
+struct SpillPage {
+    first_key: Key,
+    offset: u64,
+    len: usize,
+    /// The position of the page's first key in the run.
+    first_key_idx: usize,
+}
+
+/// A sorted run of keys in a file, with an in-memory sparse index, bloom filter and deletion
+/// bitmap.
+struct SpillRun {
+    id: usize,
+    path: PathBuf,
+    file: File,
+    pages: Vec<SpillPage>,
+    /// Rules out most keys that are not in the run without reading a page.
+    bloom: FixedBloomFilter,
+    deleted: Vec<u64>,
+    live_per_page: FenwickTree,
+    live: usize,
+}
+
+impl SpillRun {
+    /// Writes the keys, which must be sorted, to a new run file in `dir`. `expected_keys` sizes the
+    /// run's bloom filter. The file is removed again if writing fails.
+    fn write(
+        dir: &Path,
+        id: usize,
+        expected_keys: usize,
+        keys: impl Iterator<Item = io::Result<impl AsRef<[u8]>>>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("run-{id}"));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut run = Self {
+            id,
+            path,
+            file,
+            pages: Vec::new(),
+            bloom: BloomFilter::with_rate_and_hashers(
+                SPILL_BLOOM_FP_RATE,
+                max(1, expected_keys) as u32,
+                FixedHasherBuilder(0),
+                FixedHasherBuilder(1),
+            ),
+            deleted: Vec::new(),
+            live_per_page: FenwickTree::from_counts(std::iter::empty()),
+            live: 0,
+        };
+        // Dropping the run removes its file.
+        run.write_pages(keys)?;
+        return Ok(run);
+    }
+
+    fn write_pages(
+        &mut self,
+        keys: impl Iterator<Item = io::Result<impl AsRef<[u8]>>>,
+    ) -> io::Result<()> {
+        let mut writer = BufWriter::new(&self.file);
+        let mut counts = Vec::new();
+        let mut offset = 0;
+        let mut key_count = 0;
+        let mut page = SpillPageData {
+            bytes: Vec::new(),
+            ends: Vec::new(),
+        };
+        let mut first_key = None;
+        let mut keys = keys.peekable();
+        while let Some(key) = keys.next() {
+            let key = key?;
+            let key = key.as_ref();
+            first_key.get_or_insert_with(|| Key::from(key));
+            self.bloom.insert(&key);
+            page.bytes.extend_from_slice(key);
+            page.ends.push(page.bytes.len() as u32);
+            if page.bytes.len() >= SPILL_PAGE_BYTES || keys.peek().is_none() {
+                let len = page.write(&mut writer)?;
+                self.pages.push(SpillPage {
+                    first_key: first_key.take().expect("page to have a first key"),
+                    offset,
+                    len,
+                    first_key_idx: key_count,
+                });
+                counts.push(page.len());
+                offset += len as u64;
+                key_count += page.len();
+                page.bytes.clear();
+                page.ends.clear();
+            }
+        }
+        writer.flush()?;
+
+        self.deleted = vec![0; key_count.div_ceil(64)];
+        self.live_per_page = FenwickTree::from_counts(counts.into_iter());
+        self.live = key_count;
+        return Ok(());
+    }
+
+    fn is_deleted(&self, idx: usize) -> bool {
+        return self.deleted[idx / 64] & (1 << (idx % 64)) != 0;
+    }
+
+    /// The number of deleted keys at the positions in `range`.
+    fn deleted_in(&self, range: Range<usize>) -> usize {
+        let mut count = 0;
+        let mut idx = range.start;
+        while idx < range.end {
+            let bits = (64 - idx % 64).min(range.end - idx);
+            let mask = u64::MAX >> (64 - bits);
+            count += ((self.deleted[idx / 64] >> (idx % 64)) & mask).count_ones() as usize;
+            idx += bits;
+        }
+        return count;
+    }
+
+    /// A page whose first key has a rank strictly between `low` and `high`, near their middle, and
+    /// that rank. The rank is the number of live keys in the pages before, even if the first key
+    /// itself was deleted.
+    fn page_boundary_within(&self, low: usize, high: usize) -> Option<(usize, usize)> {
+        let (page_idx, _) = self.live_per_page.select((low + high) / 2);
+        return [page_idx, page_idx + 1]
+            .into_iter()
+            .filter(|&page_idx| page_idx < self.pages.len())
+            .map(|page_idx| (page_idx, self.live_per_page.prefix(page_idx)))
+            .find(|&(_, rank)| low < rank && rank < high);
+    }
+
+    /// The position in the page starting at `first` of its live key with the given rank.
+    fn nth_live(&self, first: usize, page_len: usize, mut rank: usize) -> usize {
+        let mut pos = 0;
+        // Skips whole words of the bitmap, then scans the word holding the key.
+        loop {
+            let bits = (64 - (first + pos) % 64).min(page_len - pos);
+            let live = bits - self.deleted_in(first + pos..first + pos + bits);
+            if rank < live {
+                break;
+            }
+            rank -= live;
+            pos += bits;
+        }
+        loop {
+            if !self.is_deleted(first + pos) {
+                if rank == 0 {
+                    return pos;
+                }
+                rank -= 1;
+            }
+            pos += 1;
+        }
+    }
+
+    fn read_page(&self, page_idx: usize) -> io::Result<SpillPageData> {
+        let page = &self.pages[page_idx];
+        let mut buf = vec![0; page.len];
+        (&self.file).seek(SeekFrom::Start(page.offset))?;
+        (&self.file).read_exact(&mut buf)?;
+        return Ok(SpillPageData::decode(buf));
+    }
+
+    /// Reads the live keys in order, bypassing the page cache.
+    fn live_keys(&self) -> impl Iterator<Item = io::Result<Vec<u8>>> + '_ {
+        return (0..self.pages.len()).flat_map(move |page_idx| {
+            let first = self.pages[page_idx].first_key_idx;
+            match self.read_page(page_idx) {
+                Ok(data) => (0..data.len())
+                    .filter(|&i| !self.is_deleted(first + i))
+                    .map(|i| Ok(data.key(i).to_vec()))
+                    .collect::<Vec<_>>(),
+                Err(error) => vec![Err(error)],
+            }
+        });
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self
+            .pages
+            .iter()
+            .map(|page| size_of::<SpillPage>() + page.first_key.len() + 2 * size_of::<usize>())
+            .sum::<usize>()
+            + self.bloom.num_bits() / 8
+            + self.deleted.capacity() * size_of::<u64>()
+            + self.live_per_page.tree.capacity() * size_of::<usize>();
+    }
+}
+
+impl Drop for SpillRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A bounded cache of decoded pages, evicting the least recently used page.
+struct SpillPageCache {
+    pages: HashMap<(usize, usize), (Rc<SpillPageData>, u64)>,
+    /// The cached pages by when they were last used, oldest first.
+    by_use: BTreeMap<u64, (usize, usize)>,
+    capacity: usize,
+    tick: u64,
+}
+
+impl SpillPageCache {
+    fn new(capacity: usize) -> Self {
+        return Self {
+            pages: HashMap::new(),
+            by_use: BTreeMap::new(),
+            capacity: max(1, capacity),
+            tick: 0,
+        };
+    }
+
+    fn get(&mut self, run: &SpillRun, page_idx: usize) -> io::Result<Rc<SpillPageData>> {
+        self.tick += 1;
+        let page_id = (run.id, page_idx);
+        if let Some((page, used)) = self.pages.get_mut(&page_id) {
+            self.by_use.remove(used);
+            self.by_use.insert(self.tick, page_id);
+            *used = self.tick;
+            return Ok(page.clone());
+        }
+        let page = Rc::new(run.read_page(page_idx)?);
+        while self.pages.len() >= self.capacity {
+            let (_, lru) = self.by_use.pop_first().expect("cache to be non-empty");
+            self.pages.remove(&lru);
+        }
+        self.pages.insert(page_id, (page.clone(), self.tick));
+        self.by_use.insert(self.tick, page_id);
+        return Ok(page);
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.by_use.clear();
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self
+            .pages
+            .values()
+            .map(|(page, _)| page.bytes.capacity() + page.ends.capacity() * size_of::<u32>())
+            .sum();
+    }
+}
+
+/// Where a key of `SpillKeySet` is.
+enum SpillPosition {
+    Run {
+        run_idx: usize,
+        page_idx: usize,
+        pos: usize,
+    },
+    Buffer(usize),
+}
+
+/// Keeps keys in memory up to a budget and spills the rest to sorted runs in temporary files.
+///
+/// The spilled keys are in sorted order, which a key of a given index is found in by narrowing
+/// down its rank in every run, followed by the keys still in memory. Sorting only sorts the keys
+/// in memory, which then join the sorted order, so sorted selections never rewrite the runs. Half
+/// of the budget holds keys in memory and a quarter, but at least a page per run, caches pages of
+/// the runs. The sparse page index, bloom filter and deletion bitmap of the runs, about 11 bits
+/// per spilled key, come on top.
+///
+/// I/O errors cannot be returned through `KeySet`, so the first one is kept for `take_error`, and
+/// the operation that ran into it gets an empty key or a negative answer.
+pub struct SpillKeySet {
+    buffer: ArenaKeySet,
+    buffer_budget: usize,
+    runs: Vec<SpillRun>,
+    cache: RefCell<SpillPageCache>,
+    dir: PathBuf,
+    next_run_id: usize,
+    error: RefCell<Option<io::Error>>,
+}
+
+/// Distinguishes the spill directories of key sets in the same process.
+static SPILL_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl SpillKeySet {
+    /// Creates a key set that keeps about `max_memory` bytes in memory. Runs are written to a new
+    /// directory in the system's temporary directory, which is removed when the set is dropped.
+    pub fn with_max_memory(capacity: usize, max_memory: usize) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "tectonic-spill-{}-{}",
+            std::process::id(),
+            SPILL_DIR_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let buffer_budget = max_memory / 2;
+        return Self {
+            buffer: Self::new_buffer(capacity, buffer_budget),
+            buffer_budget,
+            runs: Vec::new(),
+            // Finding a key by its rank reads a page of every run at once.
+            cache: RefCell::new(SpillPageCache::new(max(
+                max_memory / 4 / SPILL_PAGE_BYTES,
+                SPILL_MAX_RUNS + 1,
+            ))),
+            dir,
+            next_run_id: 0,
+            error: RefCell::new(None),
+        };
+    }
+
+    /// An empty buffer for up to `capacity` keys, whose index and spans take at most half of the
+    /// budget, which leaves the other half for the key bytes.
+    fn new_buffer(capacity: usize, budget: usize) -> ArenaKeySet {
+        return ArenaKeySet::new(capacity.min(ArenaKeySet::capacity_for_memory(budget / 2)));
+    }
+
+    /// Keeps the first I/O error for `take_error`.
+    fn record<T>(&self, result: io::Result<T>) -> Option<T> {
+        return match result {
+            Ok(value) => Some(value),
+            Err(error) => {
+                self.error.borrow_mut().get_or_insert(error);
+                None
+            }
+        };
+    }
+
+    fn next_run_id(&mut self) -> usize {
+        self.next_run_id += 1;
+        return self.next_run_id - 1;
+    }
+
+    /// Moves the keys in memory to a new run. The keys stay in memory if writing fails.
+    fn flush(&mut self) -> io::Result<()> {
+        self.buffer.sort();
+        let id = self.next_run_id();
+        let run = SpillRun::write(
+            &self.dir,
+            id,
+            self.buffer.len(),
+            self.buffer.iter().map(io::Result::Ok),
+        )?;
+        self.runs.push(run);
+        self.buffer = Self::new_buffer(self.buffer.len(), self.buffer_budget);
+        if self.runs.len() > SPILL_MAX_RUNS {
+            self.merge()?;
+        }
+        return Ok(());
+    }
+
+    /// Merges all runs and the keys in memory into a single sorted run. Nothing changes if writing
+    /// fails.
+    fn merge(&mut self) -> io::Result<()> {
+        self.buffer.sort();
+        let id = self.next_run_id();
+        let run = SpillRun::write(&self.dir, id, self.len(), self.sorted_keys(true))?;
+        self.runs = vec![run];
+        self.buffer = Self::new_buffer(self.buffer.len(), self.buffer_budget);
+        self.cache.get_mut().clear();
+        return Ok(());
+    }
+
+    /// The live keys of all runs in sorted order, merged with the keys in memory if `with_buffer`
+    /// is set, which must then be sorted.
+    fn sorted_keys(&self, with_buffer: bool) -> impl Iterator<Item = io::Result<Vec<u8>>> + '_ {
+        let mut sources: Vec<Box<dyn Iterator<Item = io::Result<Vec<u8>>> + '_>> = self
+            .runs
+            .iter()
+            .map(|run| Box::new(run.live_keys()) as Box<dyn Iterator<Item = _>>)
+            .collect();
+        if with_buffer {
+            sources.push(Box::new(self.buffer.iter().map(|key| Ok(key.into_owned()))));
+        }
+
+        let mut heap = BinaryHeap::new();
+        let mut first_error = None;
+        for (source_idx, source) in sources.iter_mut().enumerate() {
+            match source.next() {
+                Some(Ok(key)) => heap.push(Reverse((key, source_idx))),
+                Some(Err(error)) => first_error = first_error.or(Some(error)),
+                None => (),
+            }
+        }
+        return first_error
+            .map(Err)
+            .into_iter()
+            .chain(std::iter::from_fn(move || {
+                let Reverse((key, source_idx)) = heap.pop()?;
+                match sources[source_idx].next() {
+                    Some(Ok(next)) => heap.push(Reverse((next, source_idx))),
+                    Some(Err(error)) => return Some(Err(error)),
+                    None => (),
+                }
+                Some(Ok(key))
+            }));
+    }
+
+    /// Where the key at `idx` is. The spilled keys come first in sorted order, followed by the keys
+    /// in memory, which are merged into the sorted order while they are sorted.
+    fn locate(&self, idx: usize) -> io::Result<SpillPosition> {
+        let with_buffer = self.buffer.sorted;
+        if !with_buffer && idx >= self.spilled_len() {
+            return Ok(SpillPosition::Buffer(idx - self.spilled_len()));
+        }
+        let (source, rank) = self.select_sorted(idx, with_buffer)?;
+        if source == self.runs.len() {
+            return Ok(SpillPosition::Buffer(rank));
+        }
+        let run = &self.runs[source];
+        let (page_idx, page_rank) = run.live_per_page.select(rank);
+        let page = self.cache.borrow_mut().get(run, page_idx)?;
+        let pos = run.nth_live(run.pages[page_idx].first_key_idx, page.len(), page_rank);
+        return Ok(SpillPosition::Run {
+            run_idx: source,
+            page_idx,
+            pos,
+        });
+    }
+
+    /// The source, i.e. a run or the keys in memory after the runs, and the rank in it of the key
+    /// with rank `idx` over all sources.
+    ///
+    /// Every step takes the middle key of the source with the most candidates and counts the keys
+    /// smaller than it in every source. All keys up to it come before `idx` or all keys from it
+    /// come after, so the candidates of the source halve while no source gains any. While the
+    /// candidates of a run span pages, the first key of a page is taken instead, which is in memory.
+    fn select_sorted(&self, idx: usize, with_buffer: bool) -> io::Result<(usize, usize)> {
+        let sources = self.runs.len() + usize::from(with_buffer);
+        let mut low = vec![0; sources];
+        let mut high: Vec<usize> = (0..sources).map(|s| self.source_len(s)).collect();
+        if high.iter().filter(|&&len| len > 0).count() == 1 {
+            return Ok((high.iter().position(|&len| len > 0).unwrap(), idx));
+        }
+        let mut ranks = vec![0; sources];
+        loop {
+            let source = (0..sources)
+                .max_by_key(|&s| high[s] - low[s])
+                .expect("to have a source");
+            debug_assert!(high[source] > low[source], "idx to be in range");
+            let boundary = self.runs.get(source).and_then(|run| {
+                let (page_idx, rank) = run.page_boundary_within(low[source], high[source])?;
+                Some((&run.pages[page_idx].first_key, rank))
+            });
+            if let Some((key, own_rank)) = boundary {
+                // The first key may have been deleted, so it is only a bound on the key at `idx`.
+                for (other, rank) in ranks.iter_mut().enumerate() {
+                    *rank = if other == source {
+                        own_rank
+                    } else {
+                        self.source_rank(other, key)?
+                    };
+                }
+                let rank: usize = ranks.iter().sum();
+                for (other, &other_rank) in ranks.iter().enumerate() {
+                    if rank <= idx {
+                        low[other] = low[other].max(other_rank);
+                    } else {
+                        high[other] = high[other].min(other_rank);
+                    }
+                }
+                continue;
+            }
+            let mid = (low[source] + high[source]) / 2;
+            let key = self.source_key(source, mid)?;
+            for (other, rank) in ranks.iter_mut().enumerate() {
+                *rank = if other == source {
+                    mid
+                } else {
+                    self.source_rank(other, &key)?
+                };
+            }
+            let rank: usize = ranks.iter().sum();
+            if rank == idx {
+                return Ok((source, mid));
+            }
+            for (other, &other_rank) in ranks.iter().enumerate() {
+                if rank < idx {
+                    low[other] = low[other].max(other_rank + usize::from(other == source));
+                } else {
+                    high[other] = high[other].min(other_rank);
+                }
+            }
+        }
+    }
+
+    fn source_len(&self, source: usize) -> usize {
+        return match self.runs.get(source) {
+            Some(run) => run.live,
+            None => self.buffer.len(),
+        };
+    }
+
+    /// The key with the given rank among the live keys of the source.
+    fn source_key(&self, source: usize, rank: usize) -> io::Result<Vec<u8>> {
+        let Some(run) = self.runs.get(source) else {
+            return Ok(self.buffer.key(rank).to_vec());
+        };
+        let (page_idx, page_rank) = run.live_per_page.select(rank);
+        let page = self.cache.borrow_mut().get(run, page_idx)?;
+        let pos = run.nth_live(run.pages[page_idx].first_key_idx, page.len(), page_rank);
+        return Ok(page.key(pos).to_vec());
+    }
+
+    /// The number of live keys of the source that are smaller than `key`.
+    fn source_rank(&self, source: usize, key: &[u8]) -> io::Result<usize> {
+        let Some(run) = self.runs.get(source) else {
+            let (mut low, mut high) = (0, self.buffer.len());
+            while low < high {
+                let mid = (low + high) / 2;
+                if self.buffer.key(mid) < key {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            return Ok(low);
+        };
+        let page_idx = match run
+            .pages
+            .partition_point(|page| page.first_key.as_ref() <= key)
+        {
+            0 => return Ok(0),
+            next => next - 1,
+        };
+        let page = self.cache.borrow_mut().get(run, page_idx)?;
+        let pos = page.lower_bound(key);
+        let first = run.pages[page_idx].first_key_idx;
+        return Ok(run.live_per_page.prefix(page_idx) + pos - run.deleted_in(first..first + pos));
+    }
+
+    fn spilled_len(&self) -> usize {
+        return self.runs.iter().map(|run| run.live).sum();
+    }
+
+    fn run_contains(&self, run: &SpillRun, key: &[u8]) -> io::Result<bool> {
+        if !run.bloom.contains(&key) {
+            return Ok(false);
+        }
+        let page_idx = match run
+            .pages
+            .partition_point(|page| page.first_key.as_ref() <= key)
+        {
+            0 => return Ok(false),
+            next => next - 1,
+        };
+        let page = self.cache.borrow_mut().get(run, page_idx)?;
+        return Ok(page
+            .find(key)
+            .is_some_and(|pos| !run.is_deleted(run.pages[page_idx].first_key_idx + pos)));
+    }
+
+    fn try_remove(&mut self, idx: usize) -> io::Result<Key> {
+        let (run_idx, page_idx, pos) = match self.locate(idx)? {
+            SpillPosition::Run {
+                run_idx,
+                page_idx,
+                pos,
+            } => (run_idx, page_idx, pos),
+            SpillPosition::Buffer(buffer_idx) => return Ok(self.buffer.remove(buffer_idx)),
+        };
+        let run = &mut self.runs[run_idx];
+        let page = self.cache.get_mut().get(run, page_idx)?;
+        let key = Key::from(page.key(pos));
+        let run_pos = run.pages[page_idx].first_key_idx + pos;
+        run.deleted[run_pos / 64] |= 1 << (run_pos % 64);
+        run.live_per_page.add(page_idx, -1);
+        run.live -= 1;
+        if run.live == 0 {
+            self.runs.remove(run_idx);
+        }
+        return Ok(key);
+    }
+
+    fn try_get(&self, idx: usize) -> io::Result<Cow<'_, [u8]>> {
+        return Ok(match self.locate(idx)? {
+            SpillPosition::Run {
+                run_idx,
+                page_idx,
+                pos,
+            } => {
+                let page = self.cache.borrow_mut().get(&self.runs[run_idx], page_idx)?;
+                Cow::Owned(page.key(pos).to_vec())
+            }
+            SpillPosition::Buffer(buffer_idx) => self.buffer.get(buffer_idx),
+        });
+    }
+
+    fn try_contains(&self, key: &Key) -> io::Result<bool> {
+        if self.buffer.contains(key) {
+            return Ok(true);
+        }
+        for run in &self.runs {
+            if self.run_contains(run, key)? {
+                return Ok(true);
+            }
+        }
+        return Ok(false);
+    }
+}
+
+impl Drop for SpillKeySet {
+    fn drop(&mut self) {
+        self.runs.clear();
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+impl KeySet for SpillKeySet {
+    fn new(capacity: usize) -> Self {
+        return Self::with_max_memory(capacity, 1 << 30);
+    }
+
+    fn len(&self) -> usize {
+        return self.spilled_len() + self.buffer.len();
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    fn push(&mut self, key: Key) -> bool {
+        if self.contains(&key) {
+            return false;
+        }
+        self.buffer.push(key);
+        if self.buffer.memory_usage() > self.buffer_budget {
+            let flushed = self.flush();
+            self.record(flushed);
+        }
+        return true;
+    }
+
+    fn remove(&mut self, idx: usize) -> Key {
+        let removed = self.try_remove(idx);
+        return self
+            .record(removed)
+            .unwrap_or_else(|| Key::from([].as_slice()));
+    }
+
+    fn remove_range(&mut self, idx_range: Range<usize>) -> (Key, Key) {
+        let key1 = Key::from(self.get(idx_range.start).as_ref());
+        let key2 = Key::from(self.get(idx_range.end - 1).as_ref());
+        for idx in idx_range.rev() {
+            self.remove(idx);
+        }
+        return (key1, key2);
+    }
+
+    fn get(&self, idx: usize) -> Cow<'_, [u8]> {
+        return self.record(self.try_get(idx)).unwrap_or(Cow::Borrowed(&[]));
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        return self.record(self.try_contains(key)).unwrap_or(false);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        let with_buffer = self.buffer.sorted;
+        let buffer = (!with_buffer).then(|| self.buffer.iter());
+        return self
+            .sorted_keys(with_buffer)
+            .map_while(|key| self.record(key))
+            .map(Cow::Owned)
+            .chain(buffer.into_iter().flatten());
+    }
+
+    fn memory_usage(&self) -> usize {
+        return self.buffer.memory_usage()
+            + self.cache.borrow().memory_usage()
+            + self.runs.iter().map(SpillRun::memory_usage).sum::<usize>();
+    }
+
+    fn sort(&mut self) {
+        self.buffer.sort();
+    }
+
+    fn release_memory(&mut self) {
+        if !self.buffer.is_empty() {
+            let flushed = self.flush();
+            self.record(flushed);
+        }
+        let cache = self.cache.get_mut();
+        cache.clear();
+        cache.capacity = 1;
+    }
+
+    fn take_error(&self) -> Option<io::Error> {
+        return self.error.borrow_mut().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::seq::SliceRandom;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    fn key(n: u32) -> Key {
+        return Key::from(n.to_be_bytes().as_slice());
+    }
+
+    fn keys(set: &impl KeySet) -> Vec<Vec<u8>> {
+        return set.iter().map(|key| key.into_owned()).collect();
+    }
+
+    fn is_sorted(set: &impl KeySet) -> bool {
+        return keys(set).is_sorted();
+    }
+
+    #[test]
+    fn slot_map_remove_keeps_sorted() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(3);
+        let mut expected: Vec<u32> = (0..1_000).collect();
+        expected.shuffle(&mut rng);
+        let mut set = SlotMapKeySet::new(expected.len());
+        for &n in &expected {
+            assert!(set.push(key(n)));
+        }
+        set.sort();
+        expected.sort_unstable();
+        while expected.len() > 1 {
+            let idx = rng.random_range(0..expected.len());
+            assert_eq!(set.remove(idx), key(expected.remove(idx)));
+            assert_eq!(set.sorted_len, set.len());
+        }
+        assert_eq!(keys(&set), [key(expected[0]).to_vec()]);
+    }
+
+    #[test]
+    fn slot_map_remove_after_sort() {
+        let mut set = SlotMapKeySet::new(16);
+        for n in [5, 3, 9, 1, 7, 2, 8] {
+            assert!(set.push(key(n)));
+        }
+        set.sort();
+        assert!(is_sorted(&set));
+
+        // Removing from the sorted prefix shifts the keys after it.
+        assert_eq!(set.remove(1), key(2));
+        assert_eq!(set.len(), 6);
+        assert!(!set.contains(&key(2)));
+        assert_eq!(keys(&set), [1, 3, 5, 7, 8, 9].map(|n| key(n).to_vec()));
+
+        // Keys pushed since the last sort are swap-removed.
+        for n in [6, 0, 4] {
+            assert!(set.push(key(n)));
+        }
+        assert_eq!(set.sorted_len, 6);
+        assert_eq!(set.remove(6), key(6));
+        assert_eq!(set.get(6).as_ref(), key(4).as_ref());
+        assert_eq!(set.sorted_len, 6);
+        assert_eq!(set.remove(0), key(1));
+        assert_eq!(set.sorted_len, 5);
+
+        set.sort();
+        assert_eq!(keys(&set), [0, 3, 4, 5, 7, 8, 9].map(|n| key(n).to_vec()));
+        assert_eq!(set.sorted_len, set.len());
+    }
+
+    #[test]
+    fn spill_sorted_order_across_runs() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(4);
+        let mut shuffled: Vec<u32> = (0..20_000).map(|n| 2 * n).collect();
+        shuffled.shuffle(&mut rng);
+        let mut set = SpillKeySet::with_max_memory(shuffled.len(), 32 * 1024);
+        for &n in &shuffled {
+            assert!(set.push(key(n)));
+        }
+        assert!(!set.push(key(shuffled[0])));
+        let mut expected = shuffled;
+        expected.sort_unstable();
+        assert!(set.runs.len() > 1);
+        assert!(set.runs.iter().any(|run| run.pages.len() > 1));
+
+        // Sorting leaves the runs alone.
+        let run_ids: Vec<usize> = set.runs.iter().map(|run| run.id).collect();
+        set.sort();
+        assert_eq!(
+            set.runs.iter().map(|run| run.id).collect::<Vec<_>>(),
+            run_ids
+        );
+        assert_eq!(
+            keys(&set),
+            expected
+                .iter()
+                .map(|&n| key(n).to_vec())
+                .collect::<Vec<_>>()
+        );
+        for _ in 0..100 {
+            let idx = rng.random_range(0..expected.len());
+            assert_eq!(set.get(idx).as_ref(), key(expected[idx]).as_ref());
+        }
+
+        // Removing keys, from the runs and from memory, keeps the order.
+        for _ in 0..300 {
+            set.sort();
+            let idx = rng.random_range(0..expected.len());
+            assert_eq!(set.remove(idx), key(expected.remove(idx)));
+        }
+        set.sort();
+        assert_eq!(
+            keys(&set),
+            expected
+                .iter()
+                .map(|&n| key(n).to_vec())
+                .collect::<Vec<_>>()
+        );
+        assert!(!set.contains(&key(1)));
+        assert!(set.contains(&key(expected[0])));
+
+        // Keys pushed since the last sort come after the spilled keys until the next sort.
+        for n in [1, 3, 99_999] {
+            assert!(set.push(key(n)));
+            let pos = expected.partition_point(|&other| other < n);
+            expected.insert(pos, n);
+        }
+        set.sort();
+        for idx in [0, 1, 2, expected.len() / 2, expected.len() - 1] {
+            assert_eq!(set.get(idx).as_ref(), key(expected[idx]).as_ref());
+        }
+        assert!(set.take_error().is_none());
+    }
+
+    /// Checks the counts, minimums and fill of the nodes below `node`, and returns its depth.
+    fn check_node(node: &OrderStatisticNode, is_root: bool, check_min_fill: bool) -> usize {
+        assert!(node.entries() <= ORDER_STATISTIC_NODE_MAX);
+        if !is_root {
+            assert!(node.entries() > 0);
+            if check_min_fill {
+                assert!(node.entries() >= ORDER_STATISTIC_NODE_MIN);
+            }
+        }
+        let OrderStatisticNode::Internal(children) = node else {
+            return 0;
+        };
+        assert!(!is_root || children.len() >= 2);
+        let depths: Vec<usize> = children
+            .iter()
+            .map(|child| {
+                assert_eq!(child.len, child.node.len());
+                assert_eq!(&child.min, child.node.min());
+                check_node(&child.node, false, check_min_fill)
+            })
+            .collect();
+        assert!(depths.iter().all(|&depth| depth == depths[0]));
+        return depths[0] + 1;
+    }
+
+    fn check_tree(set: &OrderStatisticTreeKeySet, expected: &[u32], check_min_fill: bool) {
+        check_node(&set.root, true, check_min_fill);
+        assert_eq!(set.len(), expected.len());
+        assert_eq!(
+            keys(set),
+            expected
+                .iter()
+                .map(|&n| key(n).to_vec())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// A tree of the even numbers below `2 * len`, pushed in random order.
+    fn shuffled_tree(rng: &mut Xoshiro256Plus, len: u32) -> (OrderStatisticTreeKeySet, Vec<u32>) {
+        let mut expected: Vec<u32> = (0..len).map(|n| 2 * n).collect();
+        let mut shuffled = expected.clone();
+        shuffled.shuffle(rng);
+        let mut set = OrderStatisticTreeKeySet::new(0);
+        for n in shuffled {
+            assert!(set.push(key(n)));
+        }
+        expected.sort_unstable();
+        return (set, expected);
+    }
+
+    #[test]
+    fn order_statistic_tree_push_get_rank() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let (mut set, expected) = shuffled_tree(&mut rng, 10_000);
+        check_tree(&set, &expected, false);
+        assert!(!set.push(key(42)));
+        assert_eq!(set.len(), expected.len());
+
+        for (idx, &n) in expected.iter().enumerate() {
+            assert_eq!(set.get(idx).as_ref(), key(n).as_ref());
+            assert_eq!(set.rank(&key(n)), idx);
+            // An odd key is missing, and ranks after its smaller neighbour.
+            assert_eq!(set.rank(&key(n + 1)), idx + 1);
+            assert!(set.contains(&key(n)));
+            assert!(!set.contains(&key(n + 1)));
+        }
+    }
+
+    #[test]
+    fn order_statistic_tree_remove_rebalances() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(1);
+        let (mut set, mut expected) = shuffled_tree(&mut rng, 5_000);
+        // Removing keys one by one merges nodes, down to a single leaf.
+        while !expected.is_empty() {
+            let idx = rng.random_range(0..expected.len());
+            assert_eq!(set.remove(idx), key(expected.remove(idx)));
+            if expected.len() % 97 == 0 {
+                check_tree(&set, &expected, true);
+            }
+        }
+        check_tree(&set, &expected, true);
+        assert!(set.is_empty());
+        assert!(matches!(&set.root, OrderStatisticNode::Leaf(keys) if keys.is_empty()));
+        assert!(set.push(key(7)));
+        check_tree(&set, &[7], true);
+    }
+
+    #[test]
+    fn order_statistic_tree_remove_range() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(2);
+        let (mut set, mut expected) = shuffled_tree(&mut rng, 20_000);
+
+        // Within a leaf, across leaves, and across internal nodes.
+        for range_len in [1, 3, 40, 500, 3_000] {
+            let start = rng.random_range(0..=expected.len() - range_len);
+            let range = start..start + range_len;
+            let (key1, key2) = set.remove_range(range.clone());
+            assert_eq!(key1, key(expected[range.start]));
+            assert_eq!(key2, key(expected[range.end - 1]));
+            expected.drain(range);
+            check_tree(&set, &expected, false);
+        }
+
+        // The first and the last keys.
+        set.remove_range(0..10);
+        expected.drain(0..10);
+        let len = expected.len();
+        set.remove_range(len - 10..len);
+        expected.truncate(len - 10);
+        check_tree(&set, &expected, false);
+
+        // Pushing and removing single keys keeps working after range removals.
+        for n in [1, 4_001, 39_999] {
+            set.push(key(n));
+            let pos = expected.partition_point(|&other| other < n);
+            expected.insert(pos, n);
+        }
+        check_tree(&set, &expected, false);
+        for _ in 0..1_000 {
+            let idx = rng.random_range(0..expected.len());
+            assert_eq!(set.remove(idx), key(expected.remove(idx)));
+        }
+        check_tree(&set, &expected, false);
+
+        // Everything.
+        let len = set.len();
+        set.remove_range(0..len);
+        expected.clear();
+        check_tree(&set, &expected, false);
+        assert!(set.is_empty());
+    }
+}
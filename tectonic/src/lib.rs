@@ -9,15 +9,26 @@ use anyhow::{Context, Result, anyhow, bail};
 use rand::prelude::SliceRandom;
 use rand::{Rng, SeedableRng};
 use rand_xoshiro::Xoshiro256Plus;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::iter::repeat_n;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
-mod keyset;
+pub mod analyze;
+pub mod compression;
+pub mod keyset;
+pub mod oracle;
 pub mod spec;
+pub mod split;
+pub mod summary;
+pub mod trace;
 
 // Operation order to be kept for each enum/match statement
 // - insert
@@ -30,73 +41,88 @@ pub mod spec;
 // - query point empty
 // - query range
 
+use crate::compression::{Compression, WorkloadWriter};
 use crate::keyset::{
-    Key, KeySet, VecBloomFilterKeySet, VecHashMapIndexKeySet, VecKeySet, VecOptionKeySet,
+    ArenaKeySet, Key, KeySet, OrderStatisticTreeKeySet, SlotMapKeySet, SpillKeySet,
+    VecBloomFilterKeySet, VecHashMapIndexKeySet, VecHashSetKeySet, VecKeySet, key_hash,
 };
-use crate::spec::{CharacterSet, RangeFormat, StringExpr, WorkloadSpec};
+use crate::oracle::{MergeSemantics, Oracle, StateBoundary, ValueRef};
+use crate::spec::{
+    CharacterSet, GroupOrdering, Inserts, Membership, Op, RangeFormat, Selection, SelectionOrder,
+    StringExpr, WorkloadSpec,
+};
+use crate::split::{SplitOptions, SplitWriter};
+use crate::summary::{GenerationSummary, GroupSummary, KeyStats, LengthHistogram, SectionSummary};
 
 struct AsciiOperationFormatter;
 impl AsciiOperationFormatter {
-    fn write_insert(
+    fn write_section_marker(w: &mut impl Write, section: usize, name: Option<&str>) -> Result<()> {
+        w.write_all(format!("# section {section}").as_bytes())?;
+        if let Some(name) = name {
+            w.write_all(" name=".as_bytes())?;
+            w.write_all(name.as_bytes())?;
+        }
+        w.write_all("\n".as_bytes())?;
+
+        return Ok(());
+    }
+    fn write_group_marker(
         w: &mut impl Write,
-        rng: &mut impl Rng,
-        key: &Key,
-        val: &StringExpr,
-        character_set: Option<CharacterSet>,
+        section: usize,
+        group: usize,
+        name: Option<&str>,
     ) -> Result<()> {
+        w.write_all(format!("# section {section} group {group}").as_bytes())?;
+        if let Some(name) = name {
+            w.write_all(" name=".as_bytes())?;
+            w.write_all(name.as_bytes())?;
+        }
+        w.write_all("\n".as_bytes())?;
+
+        return Ok(());
+    }
+    fn write_insert<'a>(w: &mut PlannedChunk<'a>, key: &[u8], val: ValueRef<'a>) -> Result<()> {
         w.write_all("I ".as_bytes())?;
         w.write_all(key)?;
         w.write_all(" ".as_bytes())?;
-        val.write_all(w, rng, character_set)?;
+        w.push_value(val);
         w.write_all("\n".as_bytes())?;
 
         return Ok(());
     }
-    fn write_update(
-        w: &mut impl Write,
-        rng: &mut impl Rng,
-        key: &Key,
-        val: &StringExpr,
-        character_set: Option<CharacterSet>,
-    ) -> Result<()> {
+    fn write_update<'a>(w: &mut PlannedChunk<'a>, key: &[u8], val: ValueRef<'a>) -> Result<()> {
         w.write_all("U ".as_bytes())?;
         w.write_all(key)?;
         w.write_all(" ".as_bytes())?;
-        val.write_all(w, rng, character_set)?;
+        w.push_value(val);
         w.write_all("\n".as_bytes())?;
 
         return Ok(());
     }
-    fn write_merge(
-        w: &mut impl Write,
-        rng: &mut impl Rng,
-        key: &Key,
-        val: &StringExpr,
-        character_set: Option<CharacterSet>,
-    ) -> Result<()> {
+    fn write_merge<'a>(w: &mut PlannedChunk<'a>, key: &[u8], val: ValueRef<'a>) -> Result<()> {
         w.write_all("M ".as_bytes())?;
         w.write_all(key)?;
         w.write_all(" ".as_bytes())?;
-        val.write_all(w, rng, character_set)?;
+        w.push_value(val);
         w.write_all("\n".as_bytes())?;
 
         return Ok(());
     }
-    fn write_point_delete(w: &mut impl Write, key: &Key) -> Result<()> {
+    fn write_point_delete(w: &mut impl Write, key: &[u8]) -> Result<()> {
         w.write_all("D ".as_bytes())?;
         w.write_all(key)?;
         w.write_all("\n".as_bytes())?;
 
         return Ok(());
     }
-    fn write_point_query(w: &mut impl Write, key: &Key) -> Result<()> {
+    fn write_point_query(w: &mut impl Write, key: &[u8]) -> Result<()> {
         w.write_all("P ".as_bytes())?;
         w.write_all(key)?;
         w.write_all("\n".as_bytes())?;
 
         return Ok(());
     }
-    fn write_range_query(w: &mut impl Write, key1: &Key, key2: &Key) -> Result<()> {
+    fn write_range_query(w: &mut impl Write, key1: &[u8], key2: &[u8]) -> Result<()> {
         w.write_all("S ".as_bytes())?;
         w.write_all(key1)?;
         w.write_all(" ".as_bytes())?;
@@ -105,7 +131,7 @@ impl AsciiOperationFormatter {
 
         return Ok(());
     }
-    fn write_range_query_count(w: &mut impl Write, key1: &Key, count: usize) -> Result<()> {
+    fn write_range_query_count(w: &mut impl Write, key1: &[u8], count: usize) -> Result<()> {
         w.write_all("S ".as_bytes())?;
         w.write_all(key1)?;
         w.write_all(" ".as_bytes())?;
@@ -114,7 +140,7 @@ impl AsciiOperationFormatter {
 
         return Ok(());
     }
-    fn write_range_delete(w: &mut impl Write, key1: &Key, key2: &Key) -> Result<()> {
+    fn write_range_delete(w: &mut impl Write, key1: &[u8], key2: &[u8]) -> Result<()> {
         w.write_all("R ".as_bytes())?;
         w.write_all(key1)?;
         w.write_all(" ".as_bytes())?;
@@ -123,7 +149,7 @@ impl AsciiOperationFormatter {
 
         return Ok(());
     }
-    fn write_range_delete_count(w: &mut impl Write, key1: &Key, count: usize) -> Result<()> {
+    fn write_range_delete_count(w: &mut impl Write, key1: &[u8], count: usize) -> Result<()> {
         w.write_all("R ".as_bytes())?;
         w.write_all(key1)?;
         w.write_all(" ".as_bytes())?;
@@ -134,55 +160,559 @@ impl AsciiOperationFormatter {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, Ord, PartialOrd, PartialEq)]
-enum Op {
-    Insert,
-    Update,
-    Merge,
-    PointDelete,
-    PointDeleteEmpty,
-    RangeDelete,
-    PointQuery,
-    EmptyPointQuery,
-    RangeQuery,
+/// The number of operations planned into a chunk before the values of the chunk are generated.
+const CHUNK_OPERATIONS: usize = 4096;
+/// The number of chunks per thread whose values are generated at once.
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// A value to generate at `offset` of the text of a chunk, from its own RNG stream.
+struct PlannedValue<'a> {
+    offset: usize,
+    value: ValueRef<'a>,
+}
+
+/// Formatted operations whose values are generated later, so that chunks can be finished in
+/// parallel. Only the values are left, because the keys depend on every operation before them.
+#[derive(Default)]
+struct PlannedChunk<'a> {
+    text: Vec<u8>,
+    values: Vec<PlannedValue<'a>>,
+    operations: usize,
+    /// The section and group that begin with this chunk.
+    begins_group: Option<(usize, usize)>,
+}
+
+impl<'a> PlannedChunk<'a> {
+    fn push_value(&mut self, value: ValueRef<'a>) {
+        self.values.push(PlannedValue {
+            offset: self.text.len(),
+            value,
+        });
+    }
+
+    /// Writes the operations of the chunk, generating their values and recording their lengths.
+    fn write_to(&self, w: &mut impl Write, value_lengths: &mut LengthHistogram) -> Result<()> {
+        let mut written = 0;
+        for value in &self.values {
+            w.write_all(&self.text[written..value.offset])?;
+            let mut counter = CountingWriter {
+                inner: &mut *w,
+                count: 0,
+            };
+            value.value.write_to(&mut counter)?;
+            value_lengths.record(counter.count);
+            written = value.offset;
+        }
+        w.write_all(&self.text[written..])?;
+        return Ok(());
+    }
+}
+
+impl Write for PlannedChunk<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.text.extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
 }
 
+/// Counts the bytes written through it.
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count += len;
+        return Ok(len);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+/// Output for the operations of a workload, which is told where each group begins.
+pub trait OperationWriter: Write {
+    /// Called before the operations of group `group` of section `section` are written. Groups
+    /// without operations may be skipped.
+    fn begin_group(&mut self, section: usize, group: usize) -> Result<()> {
+        let _ = (section, group);
+        return Ok(());
+    }
+}
+
+impl OperationWriter for Vec<u8> {}
+impl OperationWriter for std::io::Sink {}
+impl OperationWriter for WorkloadWriter {}
+
+/// Plans operations into chunks in order, and writes the chunks in order once their values are
+/// generated. Only the values are generated in parallel. The output only depends on the seed, not
+/// on the number of threads.
+struct OperationPipeline<'a, 'w, W: OperationWriter> {
+    writer: &'w mut W,
+    threads: ValueThreads,
+    chunks: Vec<PlannedChunk<'a>>,
+    batch_len: usize,
+    value_lengths: LengthHistogram,
+}
+
+/// Where the values of planned chunks are generated.
+enum ValueThreads {
+    /// On the thread that plans the operations.
+    Calling,
+    /// On the global rayon thread pool.
+    Global,
+    /// On a thread pool with the number of threads that was asked for.
+    Owned(ThreadPool),
+}
+
+impl<'a, 'w, W: OperationWriter> OperationPipeline<'a, 'w, W> {
+    fn new(writer: &'w mut W, threads: Option<usize>) -> Result<Self> {
+        let (threads, batch_len) = match threads {
+            Some(0) => bail!("The number of threads must be at least 1"),
+            Some(1) => (ValueThreads::Calling, 1),
+            Some(thread_count) => {
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(thread_count)
+                    .build()
+                    .context("Building the thread pool")?;
+                (ValueThreads::Owned(pool), thread_count * CHUNKS_PER_THREAD)
+            }
+            None if rayon::current_num_threads() == 1 => (ValueThreads::Calling, 1),
+            None => (
+                ValueThreads::Global,
+                rayon::current_num_threads() * CHUNKS_PER_THREAD,
+            ),
+        };
+        return Ok(Self {
+            writer,
+            threads,
+            chunks: vec![PlannedChunk::default()],
+            batch_len,
+            value_lengths: LengthHistogram::default(),
+        });
+    }
+
+    /// The chunk that operations are currently planned into.
+    fn chunk(&mut self) -> &mut PlannedChunk<'a> {
+        return self.chunks.last_mut().expect("a chunk to be planned");
+    }
+
+    /// Marks the end of an operation, writing out the planned chunks once there are enough.
+    fn end_operation(&mut self) -> Result<()> {
+        let chunk = self.chunk();
+        chunk.operations += 1;
+        if chunk.operations < CHUNK_OPERATIONS {
+            return Ok(());
+        }
+        return self.next_chunk();
+    }
+
+    /// Marks the beginning of a group, whose operations are planned into a chunk of their own.
+    fn begin_group(&mut self, section: usize, group: usize) -> Result<()> {
+        if !self.chunk().text.is_empty() {
+            self.next_chunk()?;
+        }
+        self.chunk().begins_group = Some((section, group));
+        return Ok(());
+    }
+
+    /// Starts planning into a new chunk, writing out the planned chunks once there are enough.
+    fn next_chunk(&mut self) -> Result<()> {
+        if self.chunks.len() >= self.batch_len {
+            self.flush()?;
+        }
+        self.chunks.push(PlannedChunk::default());
+        return Ok(());
+    }
+
+    /// Generates the values of the planned chunks and writes them out.
+    fn flush(&mut self) -> Result<()> {
+        let chunks = std::mem::take(&mut self.chunks);
+        let render = || {
+            return chunks
+                .par_iter()
+                .map(|chunk| {
+                    let mut buf = Vec::with_capacity(chunk.text.len());
+                    let mut value_lengths = LengthHistogram::default();
+                    chunk.write_to(&mut buf, &mut value_lengths)?;
+                    return Ok((buf, value_lengths));
+                })
+                .collect::<Result<Vec<_>>>();
+        };
+        let bufs = match &self.threads {
+            ValueThreads::Calling => {
+                for chunk in &chunks {
+                    if let Some((section, group)) = chunk.begins_group {
+                        self.writer.begin_group(section, group)?;
+                    }
+                    chunk.write_to(self.writer, &mut self.value_lengths)?;
+                }
+                return Ok(());
+            }
+            ValueThreads::Global => render()?,
+            ValueThreads::Owned(pool) => pool.install(render)?,
+        };
+        for (chunk, (buf, value_lengths)) in std::iter::zip(&chunks, bufs) {
+            if let Some((section, group)) = chunk.begins_group {
+                self.writer.begin_group(section, group)?;
+            }
+            self.writer.write_all(&buf)?;
+            self.value_lengths.merge(&value_lengths);
+        }
+        return Ok(());
+    }
+
+    /// Writes out the remaining chunks, returning the lengths of all generated values.
+    fn finish(mut self) -> Result<LengthHistogram> {
+        self.flush()?;
+        return Ok(self.value_lengths);
+    }
+}
+
+/// Samples a position in [0, 1) from the density `1 + slope * (t - 0.5)`.
+fn sample_linear_position(rng: &mut impl Rng, slope: f64) -> f64 {
+    let u: f64 = rng.random();
+    if slope.abs() < 1e-9 {
+        return u;
+    }
+    // Inverse of the CDF `t + slope / 2 * (t^2 - t)`.
+    let b = 1.0 - slope / 2.0;
+    return (-b + (b * b + 2.0 * slope * u).sqrt()) / slope;
+}
+
+/// Lays out the operation markers of a group according to its ordering.
+fn order_markers(markers: &mut Vec<Op>, ordering: GroupOrdering, rng: &mut impl Rng) {
+    match ordering {
+        GroupOrdering::Shuffled => markers.shuffle(rng),
+        GroupOrdering::Sequential => {}
+        GroupOrdering::Ramp { strength } => {
+            // Each marker gets a position drawn from the density of its operation type over the
+            // group, and the markers are emitted in order of position.
+            let slope = 2.0 * strength;
+            let mut positioned: Vec<(f64, Op)> = markers
+                .iter()
+                .map(|&op| {
+                    let slope = if op.is_write() { slope } else { -slope };
+                    (sample_linear_position(rng, slope), op)
+                })
+                .collect();
+            positioned.sort_by(|a, b| a.0.total_cmp(&b.0));
+            markers.clear();
+            markers.extend(positioned.into_iter().map(|(_, op)| op));
+        }
+        GroupOrdering::Bursty { period, duty } => {
+            // Writes go to random slots of the bursts, and only overflow into the rest of the
+            // group when the bursts are full. The burst of the last, partial period is cut off at
+            // the end of the group.
+            let burst = ((period as f64 * duty).round() as usize).clamp(1, period);
+            let (mut burst_slots, mut other_slots): (Vec<usize>, Vec<usize>) =
+                (0..markers.len()).partition(|i| i % period < burst);
+            burst_slots.shuffle(rng);
+            other_slots.shuffle(rng);
+            let write_count = markers.iter().filter(|op| op.is_write()).count();
+            let mut is_write_slot = vec![false; markers.len()];
+            for &i in burst_slots.iter().chain(&other_slots).take(write_count) {
+                is_write_slot[i] = true;
+            }
+            place_markers(markers, &is_write_slot, rng);
+        }
+        GroupOrdering::Step { at, writes_before } => {
+            // Writes go to random slots on either side of the step, in the numbers that give the
+            // requested share before it, as far as the counts of the group allow.
+            let at = at.min(markers.len());
+            let write_count = markers.iter().filter(|op| op.is_write()).count();
+            let read_count = markers.len() - write_count;
+            let writes_before = ((at as f64 * writes_before).round() as usize)
+                .clamp(at.saturating_sub(read_count), write_count.min(at));
+            let mut is_write_slot = vec![false; markers.len()];
+            is_write_slot[..writes_before].fill(true);
+            is_write_slot[at..at + write_count - writes_before].fill(true);
+            is_write_slot[..at].shuffle(rng);
+            is_write_slot[at..].shuffle(rng);
+            place_markers(markers, &is_write_slot, rng);
+        }
+    }
+}
+
+/// Shuffles the writes and the reads among `markers`, and places them in the write and read
+/// slots of the group. There are as many write slots as writes.
+fn place_markers(markers: &mut Vec<Op>, is_write_slot: &[bool], rng: &mut impl Rng) {
+    let (mut writes, mut reads): (Vec<Op>, Vec<Op>) = markers.iter().partition(|op| op.is_write());
+    writes.shuffle(rng);
+    reads.shuffle(rng);
+    let (mut writes, mut reads) = (writes.into_iter(), reads.into_iter());
+    markers.clear();
+    markers.extend(is_write_slot.iter().map(|&is_write| {
+        let op = if is_write {
+            writes.next()
+        } else {
+            reads.next()
+        };
+        op.expect("as many write slots as writes")
+    }));
+}
+
+/// The number of keys generated while looking for a fresh key before giving up, unless the
+/// keyspace is small enough to warrant more attempts.
+const KEY_ATTEMPTS: usize = 1000;
+/// The most attempts spent on a fresh key in a small keyspace.
+const KEY_ATTEMPTS_MAX: usize = 1 << 20;
+
+/// Generates a key with `expr` whose hash is not in `keys_excluded` and that is not taken, counting
+/// the generated keys whose hash was in `keys_excluded`.
+///
+/// Fails once the attempts run out, which means that the keyspace of `expr` is (nearly) exhausted.
+fn generate_fresh_key(
+    expr: &StringExpr,
+    rng: &mut impl Rng,
+    character_set: Option<CharacterSet>,
+    keys_excluded: Option<&HashSet<u64>>,
+    collisions: &mut usize,
+    is_taken: impl Fn(&Key) -> bool,
+) -> Result<Key> {
+    let mut attempts = KEY_ATTEMPTS;
+    let mut cardinality = None;
+    let mut attempt = 0;
+    while attempt < attempts {
+        let key = expr.generate(rng, character_set);
+        if keys_excluded.is_some_and(|keys| keys.contains(&key_hash(&key))) {
+            *collisions += 1;
+        } else if !is_taken(&key) {
+            return Ok(key);
+        }
+
+        attempt += 1;
+        if attempt == KEY_ATTEMPTS {
+            // Only now is it worth working out the keyspace. A small one gets enough attempts to
+            // find its last few fresh keys.
+            cardinality = expr.cardinality(character_set);
+            if let Some(cardinality) = cardinality {
+                attempts = (cardinality * 16.).clamp(KEY_ATTEMPTS as f64, KEY_ATTEMPTS_MAX as f64)
+                    as usize;
+            }
+        }
+    }
+    let reason = match cardinality {
+        Some(cardinality) => {
+            format!(
+                "The key has at most {cardinality} distinct values, which are likely all taken."
+            )
+        }
+        None => "The keyspace of the key is likely exhausted.".to_string(),
+    };
+    bail!("Could not generate a fresh key in {attempts} attempts. {reason}");
+}
+
+/// Generates the key of an insert. Unique inserts also retry while `is_taken` holds for the key.
+fn generate_insert_key(
+    is: &Inserts,
+    rng: &mut impl Rng,
+    character_set: Option<CharacterSet>,
+    keys_excluded: Option<&HashSet<u64>>,
+    collisions: &mut usize,
+    is_taken: impl Fn(&Key) -> bool,
+) -> Result<Key> {
+    return generate_fresh_key(
+        &is.key,
+        rng,
+        is.character_set.or(character_set),
+        keys_excluded,
+        collisions,
+        |key| is.unique && is_taken(key),
+    );
+}
+
+/// The expected number of inserts from which `ArenaKeySet` is used, because the per key overhead of
+/// the other key sets dominates memory use.
+const ARENA_KEY_SET_MIN_INSERTS: f64 = 10_000_000.;
+
 /// Generates a workload given the spec and writes it to the given writer.
-pub fn write_operations(writer: &mut impl Write, workload: &WorkloadSpec) -> Result<()> {
-    // write_operations_with_keyset(writer, workload, VecBloomFilterKeySet::new)
+pub fn write_operations(
+    writer: &mut impl OperationWriter,
+    workload: &WorkloadSpec,
+    options: &GenerateOptions,
+) -> Result<GenerationSummary> {
+    return write_operations_with_sidecars(writer, Sidecars::default(), workload, options);
+}
+
+/// Files written next to a workload, which are fed while it is generated.
+#[derive(Default)]
+pub struct Sidecars<'w> {
+    /// Receives the expected result of every operation.
+    pub oracle: Option<&'w mut dyn Write>,
+    /// Creates the file that the state at one of `GenerateOptions::dump_state` is dumped to.
+    pub state: Option<&'w mut dyn FnMut(StateBoundary) -> Result<WorkloadWriter>>,
+}
+
+/// Generates a workload given the spec and writes it to the given writer, and its sidecars.
+pub fn write_operations_with_sidecars(
+    writer: &mut impl OperationWriter,
+    sidecars: Sidecars,
+    workload: &WorkloadSpec,
+    options: &GenerateOptions,
+) -> Result<GenerationSummary> {
+    // write_operations_with_keyset(writer, workload, options, VecBloomFilterKeySet::new)
     let has_nonempty_deletes = workload.has_delete_point() || workload.has_delete_range();
     let has_sort_heavy = workload.has_update()
         || workload.has_merge()
         || workload.has_query_point()
         || workload.has_query_range();
 
-    let has_contains_check = !workload.skip_contains_check_all()
-        && (workload.has_insert()
-            || workload.has_query_point_empty()
-            || workload.has_delete_point_empty());
+    // Unique inserts check their keys even in sections that skip the check.
+    let has_contains_check = workload.has_unique_insert()
+        || (!workload.skip_contains_check_all()
+            && (workload.has_insert()
+                || workload.has_query_point_empty()
+                || workload.has_delete_point_empty()));
+
+    // Ranges with an end key need the keys sorted, which inserts keep undoing.
+    let has_sorted_ranges =
+        workload.has_range_start_end() || (workload.has_delete_range() && workload.has_insert());
+
+    // Inserts that may repeat a key need a set that tells it apart, to count the overwrite.
+    let has_non_unique_insert = workload.has_non_unique_insert();
+
+    let membership = workload.membership;
+    let is_large = workload.expected_op_count(Op::Insert)? >= ARENA_KEY_SET_MIN_INSERTS;
+    let is_bloom = matches!(membership, Some(Membership::Bloom { .. }));
 
-    return if (has_nonempty_deletes) && (has_sort_heavy) {
-        info!("Using VecOptionKeySet");
-        write_operations_with_keyset(writer, workload, VecOptionKeySet::new)
+    return if let Some(max_memory) = options.max_memory {
+        info!(max_memory, "Using SpillKeySet");
+        write_operations_with_keyset(writer, sidecars, workload, options, |capacity| {
+            SpillKeySet::with_max_memory(capacity, max_memory)
+        })
+    } else if has_sorted_ranges {
+        info!("Using OrderStatisticTreeKeySet");
+        write_operations_with_keyset(
+            writer,
+            sidecars,
+            workload,
+            options,
+            OrderStatisticTreeKeySet::new,
+        )
+    } else if is_large && !(is_bloom && has_contains_check && !has_nonempty_deletes) {
+        info!("Using ArenaKeySet");
+        write_operations_with_keyset(writer, sidecars, workload, options, ArenaKeySet::new)
+    } else if (has_nonempty_deletes) && (has_sort_heavy) {
+        info!("Using SlotMapKeySet");
+        write_operations_with_keyset(writer, sidecars, workload, options, SlotMapKeySet::new)
     } else if has_nonempty_deletes {
         info!("Using VecHashMapIndexKeySet");
-        write_operations_with_keyset(writer, workload, VecHashMapIndexKeySet::new)
-    } else if has_contains_check {
-        info!("Using VecBloomFilterKeySet");
-        write_operations_with_keyset(writer, workload, VecBloomFilterKeySet::new)
+        write_operations_with_keyset(
+            writer,
+            sidecars,
+            workload,
+            options,
+            VecHashMapIndexKeySet::new,
+        )
+    } else if has_contains_check || has_non_unique_insert {
+        match membership {
+            Some(Membership::Exact) => {
+                info!("Using VecHashSetKeySet");
+                write_operations_with_keyset(
+                    writer,
+                    sidecars,
+                    workload,
+                    options,
+                    VecHashSetKeySet::new,
+                )
+            }
+            Some(Membership::Bloom { fp_rate }) => {
+                info!(fp_rate, "Using VecBloomFilterKeySet");
+                write_operations_with_keyset(writer, sidecars, workload, options, |capacity| {
+                    VecBloomFilterKeySet::with_rate(capacity, fp_rate)
+                })
+            }
+            None if has_non_unique_insert => {
+                info!("Using VecHashSetKeySet");
+                write_operations_with_keyset(
+                    writer,
+                    sidecars,
+                    workload,
+                    options,
+                    VecHashSetKeySet::new,
+                )
+            }
+            None => {
+                info!("Using VecBloomFilterKeySet");
+                write_operations_with_keyset(
+                    writer,
+                    sidecars,
+                    workload,
+                    options,
+                    VecBloomFilterKeySet::new,
+                )
+            }
+        }
     } else {
         info!("Using VecKeySet");
-        write_operations_with_keyset(writer, workload, VecKeySet::new)
+        write_operations_with_keyset(writer, sidecars, workload, options, VecKeySet::new)
     };
 }
 
 pub fn write_operations_with_keyset<KeySetT: KeySet>(
-    writer: &mut impl Write,
+    writer: &mut impl OperationWriter,
+    sidecars: Sidecars,
     workload: &WorkloadSpec,
+    options: &GenerateOptions,
     keyset_constructor: impl Fn(usize) -> KeySetT,
-) -> Result<()> {
-    let mut rng = Xoshiro256Plus::from_os_rng();
-    // let mut keys_prev_sections = BloomFilter::with_rate(0.01, todo!());
+) -> Result<GenerationSummary> {
+    let scale = workload.scale()?;
+    if options.phase_markers {
+        workload.check_phase_names()?;
+    }
+    let start_generation = Instant::now();
+    let seed = workload.seed.unwrap_or_else(rand::random);
+    info!(seed, "Generating with seed");
+    let mut rng = Xoshiro256Plus::seed_from_u64(seed);
+    let mut pipeline = OperationPipeline::new(writer, options.threads)?;
+    for boundary in &options.dump_state {
+        if let StateBoundary::Section(section) = boundary
+            && *section >= workload.sections.len()
+        {
+            bail!(
+                "Cannot dump the state before section {section}, the workload has {} sections",
+                workload.sections.len()
+            );
+        }
+    }
+    let Sidecars {
+        oracle: oracle_writer,
+        state: mut state_writer,
+    } = sidecars;
+    if !options.dump_state.is_empty() && state_writer.is_none() {
+        bail!("Dumping the state needs files to dump it to");
+    }
+    let mut oracle = (oracle_writer.is_some() || state_writer.is_some())
+        .then(|| Oracle::new(oracle_writer, options.merge_semantics.clone()));
+    if let (Some(max_memory), Some(_)) = (options.max_memory, &oracle) {
+        warn!(
+            max_memory,
+            "The oracle and state dumps keep every live key and its value in memory, beyond the \
+             memory budget of the valid keys"
+        );
+    }
+    let mut summary = GenerationSummary {
+        seed,
+        key_set: short_type_name::<KeySetT>(),
+        ..GenerationSummary::default()
+    };
+    let mut key_stats = KeyStats::new(options.summary);
+    let mut keys_prev_section: Option<KeySetT> = None;
+    // Hashes of the keys inserted by earlier sections, kept only while a later section has disjoint
+    // keys. Hashes bound the memory per key, and a rare false collision only regenerates a key.
+    let mut keys_prev_sections: HashSet<u64> = HashSet::new();
+    let last_disjoint_section = workload
+        .sections
+        .iter()
+        .rposition(|section| section.disjoint_keys);
     let mut time_insert = Duration::from_secs(0);
     let mut time_update = Duration::from_secs(0);
     let mut time_merge = Duration::from_secs(0);
@@ -193,21 +723,101 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
     let mut time_query_point_empty = Duration::from_secs(0);
     let mut time_query_range = Duration::from_secs(0);
 
-    for section in &workload.sections {
-        let insert_counts: Vec<usize> = section
+    for (section_idx, section) in workload.sections.iter().enumerate() {
+        if let (Some(oracle), Some(state_writer)) = (&oracle, &mut state_writer) {
+            let boundary = StateBoundary::Section(section_idx);
+            if options.dump_state.contains(&boundary) {
+                dump_state(oracle, state_writer, boundary, options.dump_values)?;
+            }
+        }
+        let mut section_summary = SectionSummary {
+            name: section.name.clone(),
+            ..SectionSummary::default()
+        };
+        let keys_disjoint = section.disjoint_keys.then_some(&keys_prev_sections);
+        let record_keys = last_disjoint_section.is_some_and(|last| section_idx < last);
+        let mut keys_section: Vec<u64> = Vec::new();
+
+        let op_counts = section
             .groups
             .iter()
-            .map(|g| {
-                g.inserts
-                    .as_ref()
-                    .map_or(0, |is| is.op_count.evaluate(&mut rng) as usize)
-            })
-            .collect();
+            .map(|g| g.op_counts(&mut rng, scale))
+            .collect::<Result<Vec<_>>>()?;
+
+        let section_insert_count: usize = op_counts
+            .iter()
+            .map(|[insert_count, ..]| insert_count)
+            .sum();
+        let inherit_fraction = section.inherit_keys.fraction()?;
+        let keys_prev = keys_prev_section.take();
+        let inherit_count = match &keys_prev {
+            Some(keys_prev) => (inherit_fraction * keys_prev.len() as f64).round() as usize,
+            None if inherit_fraction > 0. => {
+                bail!(
+                    "The first section cannot inherit keys because there is no previous section."
+                );
+            }
+            None => 0,
+        };
 
-        let mut keys_valid =
-            keyset_constructor(insert_counts.iter().sum() /*section.insert_count()*/);
+        // Only the inherited keys of the previous section are needed while building the next.
+        let mut keys_prev = keys_prev.filter(|_| inherit_count > 0);
+        if let Some(keys_prev) = &mut keys_prev {
+            keys_prev.release_memory();
+            check_key_set(keys_prev)?;
+        }
+        let mut keys_valid = keyset_constructor(section_insert_count + inherit_count);
+        summary.membership = keys_valid.membership();
+        if let Some(keys_prev) = keys_prev {
+            if inherit_count == keys_prev.len() {
+                for key in keys_prev.iter() {
+                    keys_valid.push(Key::from(key.as_ref()));
+                }
+            } else if inherit_count > 0 {
+                // Keep the inherited keys in their previous order.
+                let mut indices =
+                    rand::seq::index::sample(&mut rng, keys_prev.len(), inherit_count).into_vec();
+                indices.sort_unstable();
+                for i in indices {
+                    keys_valid.push(Key::from(keys_prev.get(i).as_ref()));
+                }
+            }
+            check_key_set(&keys_prev)?;
+            check_key_set(&keys_valid)?;
+        }
 
-        for (group, insert_count) in std::iter::zip(&section.groups, insert_counts) {
+        for (group_idx, (group, counts)) in std::iter::zip(&section.groups, op_counts).enumerate() {
+            pipeline.begin_group(section_idx, group_idx)?;
+            if options.phase_markers {
+                if group_idx == 0 {
+                    AsciiOperationFormatter::write_section_marker(
+                        pipeline.chunk(),
+                        section_idx,
+                        section.name.as_deref(),
+                    )?;
+                    if let Some(mut oracle_writer) = oracle.as_mut().and_then(Oracle::writer) {
+                        AsciiOperationFormatter::write_section_marker(
+                            &mut oracle_writer,
+                            section_idx,
+                            section.name.as_deref(),
+                        )?;
+                    }
+                }
+                AsciiOperationFormatter::write_group_marker(
+                    pipeline.chunk(),
+                    section_idx,
+                    group_idx,
+                    group.name.as_deref(),
+                )?;
+                if let Some(mut oracle_writer) = oracle.as_mut().and_then(Oracle::writer) {
+                    AsciiOperationFormatter::write_group_marker(
+                        &mut oracle_writer,
+                        section_idx,
+                        group_idx,
+                        group.name.as_deref(),
+                    )?;
+                }
+            }
             let rng_ref = &mut rng;
             let mut markers: Vec<Op> = Vec::with_capacity(0 /*group.operation_count()*/);
             let character_set = group
@@ -215,42 +825,24 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                 .or(section.character_set)
                 .or(workload.character_set);
 
-            // let insert_count = group
-            //     .inserts
-            //     .as_ref()
-            //     .map_or(0, |is| is.amount.evaluate(rng_ref) as usize);
-            let update_count = group
-                .updates
-                .as_ref()
-                .map_or(0, |us| us.op_count.evaluate(rng_ref) as usize);
-            let merge_count = group
-                .merges
-                .as_ref()
-                .map_or(0, |us| us.op_count.evaluate(rng_ref) as usize);
-            let delete_point_count = group
-                .point_deletes
-                .as_ref()
-                .map_or(0, |dps| dps.op_count.evaluate(rng_ref) as usize);
-            let delete_point_empty_count = group
-                .empty_point_deletes
-                .as_ref()
-                .map_or(0, |dpes| dpes.op_count.evaluate(rng_ref) as usize);
-            let delete_range_count = group
-                .range_deletes
-                .as_ref()
-                .map_or(0, |drs| drs.op_count.evaluate(rng_ref) as usize);
-            let query_point_count = group
-                .point_queries
-                .as_ref()
-                .map_or(0, |drs| drs.op_count.evaluate(rng_ref) as usize);
-            let query_point_empty_count = group
-                .empty_point_queries
-                .as_ref()
-                .map_or(0, |qpes| qpes.op_count.evaluate(rng_ref) as usize);
-            let query_range_count = group
-                .range_queries
-                .as_ref()
-                .map_or(0, |drs| drs.op_count.evaluate(rng_ref) as usize);
+            let [
+                insert_count,
+                update_count,
+                merge_count,
+                delete_point_count,
+                delete_point_empty_count,
+                delete_range_count,
+                query_point_count,
+                query_point_empty_count,
+                query_range_count,
+            ] = counts;
+            section_summary.groups.push(GroupSummary {
+                name: group.name.clone(),
+                op_counts: std::iter::zip(Op::ALL, counts)
+                    .filter(|&(_, count)| count > 0)
+                    .map(|(op, count)| (op.name(), count))
+                    .collect(),
+            });
 
             debug!(
                 ?insert_count,
@@ -264,6 +856,11 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                 ?query_range_count
             );
 
+            let overwrite_probability = group
+                .inserts
+                .as_ref()
+                .map_or(0., |is| is.overwrite_probability);
+
             let more_delete_point_than_keys = delete_point_count > keys_valid.len();
             if more_delete_point_than_keys {
                 bail!("Cannot have more point deletes than existing valid keys.");
@@ -275,8 +872,25 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                     .as_ref()
                     .ok_or_else(|| anyhow!("Insert spec must exist if sorted config exists"))?;
                 let mut pool = Vec::with_capacity(insert_count);
+                // Keys already in the pool, so that unique inserts do not repeat them.
+                let mut pool_keys = HashSet::new();
                 for _ in 0..insert_count {
-                    let key = is.key.generate(rng_ref, is.character_set.or(character_set));
+                    let key = generate_insert_key(
+                        is,
+                        rng_ref,
+                        character_set,
+                        keys_disjoint,
+                        &mut section_summary.key_collisions,
+                        |key| keys_valid.contains(key) || pool_keys.contains(key),
+                    )
+                    .with_context(|| {
+                        format!(
+                            "Generating a key for Insert in section {section_idx} group {group_idx}"
+                        )
+                    })?;
+                    if is.unique {
+                        pool_keys.insert(key.clone());
+                    }
                     pool.push(key);
                 }
 
@@ -309,21 +923,42 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                     .expect("inserts to exist if insert count > 0");
                 markers.extend(repeat_n(Op::Insert, insert_count - 1));
 
-                let key = key_pool
-                    .as_mut()
-                    .and_then(|pool| pool.pop())
-                    .unwrap_or_else(|| {
-                        is.key.generate(rng_ref, is.character_set.or(character_set))
-                    });
+                let key = match key_pool.as_mut().and_then(|pool| pool.pop()) {
+                    Some(key) => key,
+                    None => generate_insert_key(
+                        is,
+                        rng_ref,
+                        character_set,
+                        keys_disjoint,
+                        &mut section_summary.key_collisions,
+                        |key| keys_valid.contains(key),
+                    )
+                    .with_context(|| {
+                        format!(
+                            "Generating a key for Insert in section {section_idx} group {group_idx}"
+                        )
+                    })?,
+                };
                 // let key = is.key.generate(rng_ref, is.character_set);
-                AsciiOperationFormatter::write_insert(
-                    writer,
-                    rng_ref,
-                    &key,
-                    &is.val,
-                    is.character_set.or(character_set),
-                )?;
-                keys_valid.push(key);
+                let value = ValueRef {
+                    expr: &is.val,
+                    character_set: is.character_set.or(character_set),
+                    seed: rng_ref.random(),
+                };
+                check_key_set(&keys_valid)?;
+                AsciiOperationFormatter::write_insert(pipeline.chunk(), &key, value)?;
+                key_stats.record(&key);
+                if let Some(oracle) = &mut oracle {
+                    oracle.put("I", &key, value)?;
+                }
+                if record_keys {
+                    keys_section.push(key_hash(&key));
+                }
+                if !keys_valid.push(key) {
+                    section_summary.insert_overwrites += 1;
+                }
+                check_key_set(&keys_valid)?;
+                pipeline.end_operation()?;
             } else {
                 markers.extend(repeat_n(Op::Insert, insert_count));
             }
@@ -335,7 +970,7 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
             markers.extend(repeat_n(Op::PointQuery, query_point_count));
             markers.extend(repeat_n(Op::EmptyPointQuery, query_point_empty_count));
             markers.extend(repeat_n(Op::RangeQuery, query_range_count));
-            markers.shuffle(rng_ref);
+            order_markers(&mut markers, group.ordering, rng_ref);
 
             for (i, marker) in markers.iter().enumerate() {
                 if i.is_multiple_of(markers.len() / 10) {
@@ -351,21 +986,54 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                         let is = group.inserts.as_ref().ok_or_else(|| {
                             anyhow!("Insert marker can only appear when inserts is not None")
                         })?;
-                        let key = key_pool
-                            .as_mut()
-                            .and_then(|pool| pool.pop())
-                            .unwrap_or_else(|| {
-                                is.key.generate(rng_ref, is.character_set.or(character_set))
-                            });
+                        let overwrite =
+                            !keys_valid.is_empty() && rng_ref.random_bool(overwrite_probability);
+                        let key = if overwrite {
+                            Key::from(
+                                get_random_key(
+                                    &mut keys_valid,
+                                    rng_ref,
+                                    &is.overwrite_selection,
+                                    is.overwrite_selection_order,
+                                )?
+                                .as_ref(),
+                            )
+                        } else {
+                            match key_pool.as_mut().and_then(|pool| pool.pop()) {
+                                Some(key) => key,
+                                None => generate_insert_key(
+                                    is,
+                                    rng_ref,
+                                    character_set,
+                                    keys_disjoint,
+                                    &mut section_summary.key_collisions,
+                                    |key| keys_valid.contains(key),
+                                )
+                                .with_context(|| {
+                                    format!("Generating a key for {marker:?} in section {section_idx} group {group_idx}")
+                                })?,
+                            }
+                        };
                         // let key = is.key.generate(rng_ref, is.character_set);
-                        AsciiOperationFormatter::write_insert(
-                            writer,
-                            rng_ref,
-                            &key,
-                            &is.val,
-                            is.character_set.or(character_set),
-                        )?;
-                        keys_valid.push(key);
+                        let value = ValueRef {
+                            expr: &is.val,
+                            character_set: is.character_set.or(character_set),
+                            seed: rng_ref.random(),
+                        };
+                        check_key_set(&keys_valid)?;
+                        AsciiOperationFormatter::write_insert(pipeline.chunk(), &key, value)?;
+                        key_stats.record(&key);
+                        if let Some(oracle) = &mut oracle {
+                            oracle.put("I", &key, value)?;
+                        }
+                        if record_keys {
+                            keys_section.push(key_hash(&key));
+                        }
+                        // An overwritten key is already valid, and key sets without exact
+                        // membership would keep it twice.
+                        if overwrite || !keys_valid.push(key) {
+                            section_summary.insert_overwrites += 1;
+                        }
                         let duration = Instant::now().duration_since(start);
                         time_insert += duration;
                         if duration > Duration::from_millis(1) {
@@ -381,14 +1049,22 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                             bail!("Cannot have updates when there are no valid keys.");
                         }
                         // keys_valid.sort();
-                        let key = keys_valid.get_random(rng_ref, &us.selection);
-                        AsciiOperationFormatter::write_update(
-                            writer,
+                        let key = get_random_key(
+                            &mut keys_valid,
                             rng_ref,
-                            key,
-                            &us.val,
-                            us.character_set.or(character_set),
+                            &us.selection,
+                            us.selection_order,
                         )?;
+                        let value = ValueRef {
+                            expr: &us.val,
+                            character_set: us.character_set.or(character_set),
+                            seed: rng_ref.random(),
+                        };
+                        AsciiOperationFormatter::write_update(pipeline.chunk(), &key, value)?;
+                        key_stats.record(&key);
+                        if let Some(oracle) = &mut oracle {
+                            oracle.put("U", &key, value)?;
+                        }
                         let duration = Instant::now().duration_since(start);
                         time_update += duration;
                         if duration > Duration::from_millis(1) {
@@ -404,14 +1080,22 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                             bail!("Cannot have merges when there are no valid keys.");
                         }
                         // keys_valid.sort();
-                        let key = keys_valid.get_random(rng_ref, &ms.selection);
-                        AsciiOperationFormatter::write_merge(
-                            writer,
+                        let key = get_random_key(
+                            &mut keys_valid,
                             rng_ref,
-                            key,
-                            &ms.val,
-                            ms.character_set.or(character_set),
+                            &ms.selection,
+                            ms.selection_order,
                         )?;
+                        let value = ValueRef {
+                            expr: &ms.val,
+                            character_set: ms.character_set.or(character_set),
+                            seed: rng_ref.random(),
+                        };
+                        AsciiOperationFormatter::write_merge(pipeline.chunk(), &key, value)?;
+                        key_stats.record(&key);
+                        if let Some(oracle) = &mut oracle {
+                            oracle.merge(&key, value)?;
+                        }
                         let duration = Instant::now().duration_since(start);
                         time_merge += duration;
                         if duration > Duration::from_millis(1) {
@@ -424,9 +1108,15 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                             anyhow!("Point delete marker can only appear when updates is not None")
                         })?;
                         // keys_valid.sort();
-                        let key = keys_valid.remove_random(rng_ref, &pds.selection);
+                        let key =
+                            keys_valid.remove_random(rng_ref, &pds.selection, pds.selection_order);
 
-                        AsciiOperationFormatter::write_point_delete(writer, &key)?;
+                        check_key_set(&keys_valid)?;
+                        AsciiOperationFormatter::write_point_delete(pipeline.chunk(), &key)?;
+                        key_stats.record(&key);
+                        if let Some(oracle) = &mut oracle {
+                            oracle.point_delete(&key)?;
+                        }
                         let duration = Instant::now().duration_since(start);
                         time_delete_point += duration;
                         if duration > Duration::from_millis(1) {
@@ -442,8 +1132,17 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                             anyhow!("Point query marker can only appear when updates is not None")
                         })?;
                         // keys_valid.sort();
-                        let key = keys_valid.get_random(rng_ref, &pqs.selection);
-                        AsciiOperationFormatter::write_point_query(writer, key)?;
+                        let key = get_random_key(
+                            &mut keys_valid,
+                            rng_ref,
+                            &pqs.selection,
+                            pqs.selection_order,
+                        )?;
+                        AsciiOperationFormatter::write_point_query(pipeline.chunk(), &key)?;
+                        key_stats.record(&key);
+                        if let Some(oracle) = &mut oracle {
+                            oracle.point_query(&key)?;
+                        }
                         let duration = Instant::now().duration_since(start);
                         time_query_point += duration;
                         if duration > Duration::from_millis(1) {
@@ -455,16 +1154,24 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                         let epd = group.empty_point_deletes.as_ref().ok_or_else(|| {
                             anyhow!("Empty point delete marker can only appear when empty_point_deletes is not None")
                         })?;
-                        let key = loop {
-                            let k = epd
-                                .key
-                                .generate(rng_ref, epd.character_set.or(character_set));
-                            if !keys_valid.contains(&k) {
-                                break k;
-                            }
-                        };
+                        let key = generate_fresh_key(
+                            &epd.key,
+                            rng_ref,
+                            epd.character_set.or(character_set),
+                            keys_disjoint,
+                            &mut section_summary.key_collisions,
+                            |key| !section.skip_key_contains_check && keys_valid.contains(key),
+                        )
+                        .with_context(|| {
+                            format!("Generating a key for {marker:?} in section {section_idx} group {group_idx}")
+                        })?;
 
-                        AsciiOperationFormatter::write_point_delete(writer, &key)?;
+                        check_key_set(&keys_valid)?;
+                        AsciiOperationFormatter::write_point_delete(pipeline.chunk(), &key)?;
+                        key_stats.record(&key);
+                        if let Some(oracle) = &mut oracle {
+                            oracle.point_delete(&key)?;
+                        }
                         let duration = Instant::now().duration_since(start);
                         time_delete_point_empty += duration;
                         if duration > Duration::from_millis(1) {
@@ -477,14 +1184,24 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                             anyhow!("Empty point query marker can only appear when empty_point_queries is not None")
                         })?;
                         let char_set = epq.character_set.or(character_set);
-                        let key = loop {
-                            let k = epq.key.generate(rng_ref, char_set);
-                            if !keys_valid.contains(&k) {
-                                break k;
-                            }
-                        };
+                        let key = generate_fresh_key(
+                            &epq.key,
+                            rng_ref,
+                            char_set,
+                            keys_disjoint,
+                            &mut section_summary.key_collisions,
+                            |key| !section.skip_key_contains_check && keys_valid.contains(key),
+                        )
+                        .with_context(|| {
+                            format!("Generating a key for {marker:?} in section {section_idx} group {group_idx}")
+                        })?;
 
-                        AsciiOperationFormatter::write_point_query(writer, &key)?;
+                        check_key_set(&keys_valid)?;
+                        AsciiOperationFormatter::write_point_query(pipeline.chunk(), &key)?;
+                        key_stats.record(&key);
+                        if let Some(oracle) = &mut oracle {
+                            oracle.point_query(&key)?;
+                        }
                         let duration = Instant::now().duration_since(start);
                         time_query_point_empty += duration;
                         if duration > Duration::from_millis(1) {
@@ -505,19 +1222,42 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                         let sel = rqs.selectivity.evaluate(rng_ref);
                         match rqs.range_format {
                             RangeFormat::StartCount => {
-                                let key = keys_valid.get_random(rng_ref, &rqs.selection);
-
                                 let count = (sel * keys_valid.len() as f64) as usize;
+                                let key = get_random_key(
+                                    &mut keys_valid,
+                                    rng_ref,
+                                    &rqs.selection,
+                                    rqs.selection_order.unwrap_or_default(),
+                                )?;
                                 AsciiOperationFormatter::write_range_query_count(
-                                    writer, key, count,
-                                )?
+                                    pipeline.chunk(),
+                                    &key,
+                                    count,
+                                )?;
+                                key_stats.record(&key);
+                                if let Some(oracle) = &mut oracle {
+                                    oracle.range_query_count(&key, count)?;
+                                }
                             }
                             RangeFormat::StartEnd => {
                                 keys_valid.sort();
-                                let (key1, key2) =
-                                    keys_valid.get_range_random(sel, rng_ref, &rqs.selection);
-
-                                AsciiOperationFormatter::write_range_query(writer, key1, key2)?
+                                let (key1, key2) = get_range_random_keys(
+                                    &mut keys_valid,
+                                    sel,
+                                    rng_ref,
+                                    &rqs.selection,
+                                    rqs.selection_order.unwrap_or(SelectionOrder::Sorted),
+                                )?;
+                                AsciiOperationFormatter::write_range_query(
+                                    pipeline.chunk(),
+                                    &key1,
+                                    &key2,
+                                )?;
+                                key_stats.record(&key1);
+                                key_stats.record(&key2);
+                                if let Some(oracle) = &mut oracle {
+                                    oracle.range_query(&key1, &key2)?;
+                                }
                             }
                         }
                         let duration = Instant::now().duration_since(start);
@@ -540,19 +1280,42 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                         let sel = rds.selectivity.evaluate(rng_ref);
                         match rds.range_format {
                             RangeFormat::StartCount => {
-                                let key = keys_valid.get_random(rng_ref, &rds.selection);
-
                                 let count = (sel * keys_valid.len() as f64) as usize;
+                                let key = get_random_key(
+                                    &mut keys_valid,
+                                    rng_ref,
+                                    &rds.selection,
+                                    rds.selection_order.unwrap_or_default(),
+                                )?;
                                 AsciiOperationFormatter::write_range_delete_count(
-                                    writer, key, count,
-                                )?
+                                    pipeline.chunk(),
+                                    &key,
+                                    count,
+                                )?;
+                                key_stats.record(&key);
+                                if let Some(oracle) = &mut oracle {
+                                    oracle.range_delete_count(&key, count)?;
+                                }
                             }
                             RangeFormat::StartEnd => {
                                 keys_valid.sort();
-                                let (key1, key2) =
-                                    keys_valid.get_range_random(sel, rng_ref, &rds.selection);
-
-                                AsciiOperationFormatter::write_range_delete(writer, key1, key2)?
+                                let (key1, key2) = get_range_random_keys(
+                                    &mut keys_valid,
+                                    sel,
+                                    rng_ref,
+                                    &rds.selection,
+                                    rds.selection_order.unwrap_or(SelectionOrder::Sorted),
+                                )?;
+                                AsciiOperationFormatter::write_range_delete(
+                                    pipeline.chunk(),
+                                    &key1,
+                                    &key2,
+                                )?;
+                                key_stats.record(&key1);
+                                key_stats.record(&key2);
+                                if let Some(oracle) = &mut oracle {
+                                    oracle.range_delete(&key1, &key2)?;
+                                }
                             }
                         }
                         let duration = Instant::now().duration_since(start);
@@ -562,8 +1325,25 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
                         }
                     }
                 }
+                check_key_set(&keys_valid)?;
+                pipeline.end_operation()?;
             }
         }
+        if section_summary.key_collisions > 0 {
+            warn!(
+                section = section_idx,
+                key_collisions = section_summary.key_collisions,
+                "Regenerated keys that collided with keys inserted by earlier sections"
+            );
+        }
+        section_summary.live_keys = keys_valid.len();
+        section_summary.key_set_memory = keys_valid.memory_usage();
+        section_summary.key_set_memory_per_key =
+            section_summary.key_set_memory / section_summary.live_keys.max(1);
+        keys_prev_section = Some(keys_valid);
+        keys_prev_sections.extend(keys_section);
+        summary.live_keys = section_summary.live_keys;
+        summary.sections.push(section_summary);
     }
     debug!(
         insert = %time_insert.as_secs_f64(),
@@ -577,21 +1357,300 @@ pub fn write_operations_with_keyset<KeySetT: KeySet>(
         query_range = %time_query_range.as_secs_f64(),
         "operation generation timings (in seconds)"
     );
+    summary.value_lengths = pipeline.finish()?;
+    if let (Some(oracle), Some(state_writer)) = (&oracle, &mut state_writer)
+        && options.dump_state.contains(&StateBoundary::End)
+    {
+        dump_state(
+            oracle,
+            state_writer,
+            StateBoundary::End,
+            options.dump_values,
+        )?;
+    }
+    summary.distinct_keys = key_stats.distinct();
+    summary.key_lengths = key_stats.lengths;
+    let op_times = [
+        time_insert,
+        time_update,
+        time_merge,
+        time_delete_point,
+        time_delete_point_empty,
+        time_delete_range,
+        time_query_point,
+        time_query_point_empty,
+        time_query_range,
+    ];
+    summary.timings = std::iter::zip(Op::ALL, op_times)
+        .map(|(op, time)| (op.name(), time.as_secs_f64()))
+        .chain([("total", start_generation.elapsed().as_secs_f64())])
+        .collect();
 
-    return Ok(());
+    return Ok(summary);
 }
 
-/// Takes in a JSON representation of a workload specification and writes the workload to a file.
-pub fn generate_workload(workload_spec_string: &str, output_file: &PathBuf) -> Result<()> {
-    let workload_spec: WorkloadSpec =
-        serde_json::from_str(workload_spec_string).context("Parsing spec file")?;
-    let mut buf_writer = BufWriter::with_capacity(1024 * 1024, File::create(output_file)?);
-    write_operations(&mut buf_writer, &workload_spec)?;
-    buf_writer.flush()?;
+/// Picks a valid key like `KeySet::get_random`, failing instead of handing out an empty key if the
+/// key set ran into an I/O error.
+fn get_random_key<'k, KeySetT: KeySet>(
+    keys: &'k mut KeySetT,
+    rng: &mut impl Rng,
+    selection: &Selection,
+    order: SelectionOrder,
+) -> Result<Cow<'k, [u8]>> {
+    let idx = keys.select(rng, selection, order, keys.len());
+    let keys: &'k KeySetT = keys;
+    let key = keys.get(idx);
+    check_key_set(keys)?;
+    return Ok(key);
+}
+
+/// The start and end key of a range.
+type KeyRange<'k> = (Cow<'k, [u8]>, Cow<'k, [u8]>);
+
+/// Picks the keys around a range like `KeySet::get_range_random`, failing if the key set ran into
+/// an I/O error.
+fn get_range_random_keys<'k, KeySetT: KeySet>(
+    keys: &'k mut KeySetT,
+    selectivity: f64,
+    rng: &mut impl Rng,
+    selection: &Selection,
+    order: SelectionOrder,
+) -> Result<KeyRange<'k>> {
+    let idx_range = keys.select_range(selectivity, rng, selection, order);
+    let keys: &'k KeySetT = keys;
+    let key1 = keys.get(idx_range.start);
+    let key2 = keys.get(idx_range.end);
+    check_key_set(keys)?;
+    return Ok((key1, key2));
+}
+
+/// Fails with the first I/O error the key set ran into, if any. Operations check it before they
+/// are written, because a set that ran into an error hands out empty keys.
+fn check_key_set(keys: &impl KeySet) -> Result<()> {
+    return match keys.take_error() {
+        Some(error) => Err(error).context("Accessing the valid keys on disk"),
+        None => Ok(()),
+    };
+}
+
+/// The name of a type without its path, e.g. `VecKeySet`.
+fn short_type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split_once('<').map_or(name, |(name, _)| name);
+    return name.rsplit("::").next().unwrap_or(name);
+}
+
+/// Dumps the live keys, and their values if `values` is set, to the file for `boundary`.
+fn dump_state(
+    oracle: &Oracle,
+    state_writer: &mut dyn FnMut(StateBoundary) -> Result<WorkloadWriter>,
+    boundary: StateBoundary,
+    values: bool,
+) -> Result<()> {
+    info!(
+        boundary = boundary.name(),
+        live_keys = oracle.len(),
+        "Dumping state"
+    );
+    let mut writer = state_writer(boundary)?;
+    oracle.dump(&mut writer, values)?;
+    return writer.finish();
+}
+
+/// Options for generating a workload that are not part of the workload spec.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateOptions {
+    /// Multiplies the `scale` of the workload spec.
+    pub scale: Option<f64>,
+    /// Also scales the `n` of Zipf key selections and the `amount` of hot ranges.
+    pub scale_parameters: bool,
+    /// Memory budget for the valid keys, in bytes. Keys beyond it are spilled to temporary files.
+    pub max_memory: Option<usize>,
+    /// Seed for the random number generator, overriding the `seed` of the workload spec.
+    pub seed: Option<u64>,
+    /// Number of threads that generate values. Defaults to the global rayon thread pool. Keys are
+    /// chosen and operations formatted on the calling thread. The workload is the same for any
+    /// number of threads.
+    pub threads: Option<usize>,
+    /// How the output file is compressed. Defaults to the compression of its extension, e.g.
+    /// `.txt.zst`.
+    pub compression: Option<Compression>,
+    /// How the output is split into multiple files.
+    pub split: SplitOptions,
+    /// Writes a marker line, e.g. `# section 1 group 0 name=run`, where each section and group
+    /// begins.
+    pub phase_markers: bool,
+    /// Writes the expected result of every operation to a sidecar file, e.g. `name.oracle.txt`.
+    pub oracle: bool,
+    /// How the oracle combines merge operands with the value of their key.
+    pub merge_semantics: MergeSemantics,
+    /// Dumps the live keys in sorted order before these sections or at the end, to sidecar files,
+    /// e.g. `name.state.s1.txt` or `name.state.end.txt`.
+    pub dump_state: Vec<StateBoundary>,
+    /// Also dumps the value of each live key.
+    pub dump_values: bool,
+    /// Writes statistics and provenance of the workload to a sidecar file, e.g.
+    /// `name.summary.json`.
+    pub summary: bool,
+}
+
+/// Writes the workload of a parsed workload specification to a file.
+///
+/// Compressed output is compressed on a separate thread. Split output is written to numbered files
+/// next to `output_file`, along with a manifest of them. The oracle, state dumps and summary are
+/// written next to `output_file` as well.
+pub fn generate_workload(
+    workload_spec: &ParsedWorkloadSpec,
+    output_file: &Path,
+    options: &GenerateOptions,
+) -> Result<()> {
+    let compression = options
+        .compression
+        .unwrap_or_else(|| Compression::from_path(output_file));
+    let mut oracle = if options.oracle {
+        let (dir, stem) = output_stem(output_file)?;
+        let extension = workload_extension(compression);
+        let oracle_file = dir.join(format!("{stem}.oracle.{extension}"));
+        Some(WorkloadWriter::create(&oracle_file, compression)?)
+    } else {
+        None
+    };
+    let mut create_state_file = |boundary: StateBoundary| {
+        let (dir, stem) = output_stem(output_file)?;
+        let extension = workload_extension(compression);
+        let state_file = dir.join(format!("{stem}.state.{}.{extension}", boundary.name()));
+        return WorkloadWriter::create(&state_file, compression);
+    };
+    let sidecars = Sidecars {
+        oracle: oracle.as_mut().map(|writer| writer as &mut dyn Write),
+        state: (!options.dump_state.is_empty()).then_some(
+            &mut create_state_file as &mut dyn FnMut(StateBoundary) -> Result<WorkloadWriter>,
+        ),
+    };
+
+    let mut summary = if options.split.is_split() {
+        let mut writer = SplitWriter::new(output_file, compression, options.split.clone())?;
+        let summary =
+            write_operations_with_sidecars(&mut writer, sidecars, &workload_spec.spec, options)?;
+        let manifest = writer.finish()?;
+        info!(files = manifest.parts.len(), "Split workload");
+        summary
+    } else {
+        let mut writer = WorkloadWriter::create(output_file, compression)?;
+        let summary =
+            write_operations_with_sidecars(&mut writer, sidecars, &workload_spec.spec, options)?;
+        writer.finish()?;
+        summary
+    };
+    if let Some(oracle) = oracle {
+        oracle.finish().context("Writing oracle")?;
+    }
+    summary.log();
+    if options.summary {
+        summary.spec_sha256 = Some(
+            Sha256::digest(workload_spec.json)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        );
+        let (dir, stem) = output_stem(output_file)?;
+        let summary_file = dir.join(format!("{stem}.summary.json"));
+        let file = File::create(&summary_file)
+            .with_context(|| format!("Creating {}", summary_file.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &summary).context("Writing summary")?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Writes the workload of a parsed workload specification to `writer`, e.g. stdout.
+pub fn generate_workload_to(
+    workload_spec: &ParsedWorkloadSpec,
+    mut writer: WorkloadWriter,
+    options: &GenerateOptions,
+) -> Result<()> {
+    if options.split.is_split() {
+        bail!("Split output can only be written to files");
+    }
+    if options.oracle || !options.dump_state.is_empty() || options.summary {
+        bail!("The oracle, state dumps and summary can only be written next to an output file");
+    }
+    let summary = write_operations(&mut writer, &workload_spec.spec, options)?;
+    writer.finish()?;
+    summary.log();
 
     Ok(())
 }
 
+/// The directory of an output file and its name without extension, e.g. `name` of
+/// `name.txt.zst`, which the files written next to it are named after.
+pub(crate) fn output_stem(path: &Path) -> Result<(PathBuf, String)> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Invalid output file name {}", path.display()))?;
+    let name = match Compression::from_path(path) {
+        Compression::None => file_name,
+        _ => file_name
+            .rsplit_once('.')
+            .map_or(file_name, |(name, _)| name),
+    };
+    let stem = name.strip_suffix(".txt").unwrap_or(name);
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    return Ok((dir, stem.to_string()));
+}
+
+/// The extension of workload files, e.g. `txt.zst`.
+pub(crate) fn workload_extension(compression: Compression) -> String {
+    return match compression.extension() {
+        Some(extension) => format!("txt.{extension}"),
+        None => "txt".to_string(),
+    };
+}
+
+/// A workload spec parsed from its JSON, with the options that override it applied. Generating it
+/// takes the same options.
+pub struct ParsedWorkloadSpec<'s> {
+    /// The JSON the spec was parsed from, whose hash the summary records.
+    json: &'s str,
+    spec: WorkloadSpec,
+}
+
+impl<'s> ParsedWorkloadSpec<'s> {
+    pub fn parse(json: &'s str, options: &GenerateOptions) -> Result<Self> {
+        let spec = parse_workload_spec(json, options)?;
+        return Ok(Self { json, spec });
+    }
+
+    /// The scale that generating the spec applies, i.e. the `scale` of the spec times the one of
+    /// the options, or `None` if neither sets one.
+    pub fn scale(&self) -> Option<f64> {
+        return self.spec.scale;
+    }
+}
+
+/// Parses a workload spec and applies the options that override it.
+fn parse_workload_spec(
+    workload_spec_string: &str,
+    options: &GenerateOptions,
+) -> Result<WorkloadSpec> {
+    let mut workload_spec: WorkloadSpec =
+        serde_json::from_str(workload_spec_string).context("Parsing spec file")?;
+    if let Some(scale) = options.scale {
+        workload_spec.scale = Some(workload_spec.scale.unwrap_or(1.) * scale);
+    }
+    workload_spec.scale_parameters |= options.scale_parameters;
+    workload_spec.apply_parameter_scale()?;
+    if let Some(seed) = options.seed {
+        workload_spec.seed = Some(seed);
+    }
+    workload_spec.apply_seed();
+    return Ok(workload_spec);
+}
+
 pub fn generate_workload_spec_schema() -> serde_json::Result<String> {
     let schema = schemars::schema_for!(WorkloadSpec);
     return serde_json::to_string_pretty(&schema);
@@ -0,0 +1,367 @@
+#![allow(clippy::needless_return)]
+
+use crate::keyset::Key;
+use crate::spec::{CharacterSet, StringExpr};
+use anyhow::{Result, anyhow, bail};
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro256Plus;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::Bound;
+use std::str::FromStr;
+
+/// A point in a workload at which its state can be dumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateBoundary {
+    /// Before the section with this index.
+    Section(usize),
+    /// After the last section.
+    End,
+}
+
+impl StateBoundary {
+    /// The name of the boundary in file names, e.g. `s1` or `end`.
+    pub fn name(&self) -> String {
+        return match self {
+            Self::Section(section) => format!("s{section}"),
+            Self::End => "end".to_string(),
+        };
+    }
+}
+
+impl FromStr for StateBoundary {
+    type Err = anyhow::Error;
+
+    /// Parses a section index or `end`.
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "end" {
+            return Ok(Self::End);
+        }
+        return s
+            .parse()
+            .map(Self::Section)
+            .map_err(|_| anyhow!("Unknown state boundary {s}, expected a section index or end"));
+    }
+}
+
+/// How a merge combines its operand with the value of its key.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum MergeSemantics {
+    /// The operand replaces the value, like an update.
+    #[default]
+    Replace,
+    /// The operand is appended to the value, after the delimiter.
+    Append { delimiter: String },
+}
+
+impl FromStr for MergeSemantics {
+    type Err = anyhow::Error;
+
+    /// Parses `replace`, `append`, or `append:<delimiter>`, e.g. `append:,`.
+    fn from_str(s: &str) -> Result<Self> {
+        return match s.split_once(':') {
+            None if s == "replace" => Ok(Self::Replace),
+            None if s == "append" => Ok(Self::Append {
+                delimiter: String::new(),
+            }),
+            Some(("append", delimiter)) => Ok(Self::Append {
+                delimiter: delimiter.to_string(),
+            }),
+            _ => {
+                bail!("Unknown merge semantics {s}, expected replace, append or append:<delimiter>")
+            }
+        };
+    }
+}
+
+/// A generated value, which is generated again when it is needed.
+#[derive(Clone, Copy)]
+pub(crate) struct ValueRef<'a> {
+    pub expr: &'a StringExpr,
+    pub character_set: Option<CharacterSet>,
+    pub seed: u64,
+}
+
+impl ValueRef<'_> {
+    pub fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        let mut rng = Xoshiro256Plus::seed_from_u64(self.seed);
+        return self.expr.write_all(w, &mut rng, self.character_set);
+    }
+}
+
+/// Keeps the full key to value state of a workload while it is generated. With a writer, it writes
+/// the expected result of every operation, one line per line of the workload.
+///
+/// Writes get their operation code, e.g. `I`. Point queries get `P found <value>` or
+/// `P missing`. Range queries get `S <count> <first key> <last key>`, or `S 0` for an empty range.
+/// Ranges with an end key exclude it. Phase markers are repeated, so that lines stay aligned.
+pub(crate) struct Oracle<'a, 'w> {
+    writer: Option<&'w mut dyn Write>,
+    merge: MergeSemantics,
+    /// The value of each live key, as the operands since its last insert or update.
+    state: BTreeMap<Key, Vec<ValueRef<'a>>>,
+}
+
+impl<'a, 'w> Oracle<'a, 'w> {
+    pub fn new(writer: Option<&'w mut dyn Write>, merge: MergeSemantics) -> Self {
+        return Self {
+            writer,
+            merge,
+            state: BTreeMap::new(),
+        };
+    }
+
+    /// Where lines of the workload that are not operations, e.g. phase markers, are repeated.
+    pub fn writer(&mut self) -> Option<&mut (dyn Write + 'w)> {
+        return self.writer.as_deref_mut();
+    }
+
+    /// An insert or update.
+    pub fn put(&mut self, code: &str, key: &[u8], value: ValueRef<'a>) -> Result<()> {
+        match self.state.get_mut(key) {
+            Some(values) => {
+                values.clear();
+                values.push(value);
+            }
+            None => {
+                self.state.insert(Key::from(key), vec![value]);
+            }
+        }
+        return self.write_code(code);
+    }
+
+    pub fn merge(&mut self, key: &[u8], value: ValueRef<'a>) -> Result<()> {
+        match (self.state.get_mut(key), &self.merge) {
+            (Some(values), MergeSemantics::Append { .. }) => values.push(value),
+            (Some(values), MergeSemantics::Replace) => {
+                values.clear();
+                values.push(value);
+            }
+            (None, _) => {
+                self.state.insert(Key::from(key), vec![value]);
+            }
+        }
+        return self.write_code("M");
+    }
+
+    pub fn point_delete(&mut self, key: &[u8]) -> Result<()> {
+        self.state.remove(key);
+        return self.write_code("D");
+    }
+
+    pub fn range_delete(&mut self, start: &[u8], end: &[u8]) -> Result<()> {
+        let keys: Vec<Key> = self
+            .range(start, Bound::Excluded(end))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.state.remove(&key);
+        }
+        return self.write_code("R");
+    }
+
+    pub fn range_delete_count(&mut self, start: &[u8], count: usize) -> Result<()> {
+        let keys: Vec<Key> = self
+            .range(start, Bound::Unbounded)
+            .take(count)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.state.remove(&key);
+        }
+        return self.write_code("R");
+    }
+
+    pub fn point_query(&mut self, key: &[u8]) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        let Some(values) = self.state.get(key) else {
+            writer.write_all(b"P missing\n")?;
+            return Ok(());
+        };
+        writer.write_all(b"P found ")?;
+        write_value(writer, values, &self.merge)?;
+        writer.write_all(b"\n")?;
+        return Ok(());
+    }
+
+    pub fn range_query(&mut self, start: &[u8], end: &[u8]) -> Result<()> {
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        let keys = self.range(start, Bound::Excluded(end));
+        let (count, first, last) = count_first_last(keys.map(|(key, _)| key));
+        return self.write_range(count, first, last);
+    }
+
+    pub fn range_query_count(&mut self, start: &[u8], count: usize) -> Result<()> {
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        let keys = self.range(start, Bound::Unbounded).take(count);
+        let (count, first, last) = count_first_last(keys.map(|(key, _)| key));
+        return self.write_range(count, first, last);
+    }
+
+    /// Writes the live keys in sorted order, one per line, each followed by its value if `values`
+    /// is set.
+    pub fn dump(&self, w: &mut impl Write, values: bool) -> Result<()> {
+        for (key, key_values) in &self.state {
+            w.write_all(key)?;
+            if values {
+                w.write_all(b" ")?;
+                write_value(w, key_values, &self.merge)?;
+            }
+            w.write_all(b"\n")?;
+        }
+        return Ok(());
+    }
+
+    /// The number of live keys.
+    pub fn len(&self) -> usize {
+        return self.state.len();
+    }
+
+    fn range(
+        &self,
+        start: &[u8],
+        end: Bound<&[u8]>,
+    ) -> impl Iterator<Item = (&Key, &Vec<ValueRef<'a>>)> {
+        return self.state.range::<[u8], _>((Bound::Included(start), end));
+    }
+
+    fn write_range(&mut self, count: usize, first: Option<Key>, last: Option<Key>) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        writer.write_all(format!("S {count}").as_bytes())?;
+        if let (Some(first), Some(last)) = (first, last) {
+            writer.write_all(b" ")?;
+            writer.write_all(&first)?;
+            writer.write_all(b" ")?;
+            writer.write_all(&last)?;
+        }
+        writer.write_all(b"\n")?;
+        return Ok(());
+    }
+
+    fn write_code(&mut self, code: &str) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        writer.write_all(code.as_bytes())?;
+        writer.write_all(b"\n")?;
+        return Ok(());
+    }
+}
+
+/// Writes the value made of the operands of a key.
+fn write_value(w: &mut impl Write, values: &[ValueRef], merge: &MergeSemantics) -> Result<()> {
+    for (i, value) in values.iter().enumerate() {
+        if let (true, MergeSemantics::Append { delimiter }) = (i > 0, merge) {
+            w.write_all(delimiter.as_bytes())?;
+        }
+        value.write_to(w)?;
+    }
+    return Ok(());
+}
+
+/// Counts the keys, keeping the first and last of them.
+fn count_first_last<'k>(
+    mut keys: impl Iterator<Item = &'k Key>,
+) -> (usize, Option<Key>, Option<Key>) {
+    let Some(first) = keys.next() else {
+        return (0, None, None);
+    };
+    let (count, last) = keys.fold((1, first), |(count, _), key| (count + 1, key));
+    return (count, Some(first.clone()), Some(last.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{GenerateOptions, Sidecars, parse_workload_spec, write_operations_with_sidecars};
+
+    #[test]
+    fn oracle_matches_workload() {
+        let spec = r#"{
+            "character_set": "alphanumeric",
+            "seed": 3,
+            "sections": [{
+                "groups": [
+                    {
+                        "inserts": {
+                            "op_count": 2000,
+                            "key": { "uniform": { "len": 4 } },
+                            "val": { "uniform": { "len": 6 } }
+                        }
+                    },
+                    {
+                        "inserts": {
+                            "op_count": 200,
+                            "key": { "uniform": { "len": 4 } },
+                            "val": { "uniform": { "len": 6 } }
+                        },
+                        "updates": { "op_count": 500, "val": { "uniform": { "len": 6 } } },
+                        "point_deletes": { "op_count": 200 },
+                        "point_queries": { "op_count": 1000 },
+                        "empty_point_queries": {
+                            "op_count": 100,
+                            "key": { "uniform": { "len": 4 } }
+                        }
+                    }
+                ]
+            }]
+        }"#;
+        let options = GenerateOptions {
+            phase_markers: true,
+            oracle: true,
+            ..GenerateOptions::default()
+        };
+        let workload_spec = parse_workload_spec(spec, &options).unwrap();
+        let mut workload = Vec::new();
+        let mut oracle = Vec::new();
+        let sidecars = Sidecars {
+            oracle: Some(&mut oracle),
+            state: None,
+        };
+        write_operations_with_sidecars(&mut workload, sidecars, &workload_spec, &options).unwrap();
+
+        let workload = String::from_utf8(workload).unwrap();
+        let oracle = String::from_utf8(oracle).unwrap();
+        let workload_lines: Vec<&str> = workload.lines().collect();
+        let oracle_lines: Vec<&str> = oracle.lines().collect();
+        assert_eq!(workload_lines.len(), oracle_lines.len());
+
+        let mut values: HashMap<&str, &str> = HashMap::new();
+        let mut found = 0;
+        for (line, expected) in std::iter::zip(workload_lines, oracle_lines) {
+            if line.starts_with('#') {
+                assert_eq!(line, expected);
+                continue;
+            }
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields[..] {
+                ["I" | "U", key, value] => {
+                    values.insert(key, value);
+                    assert_eq!(expected, fields[0]);
+                }
+                ["D", key] => {
+                    values.remove(key);
+                    assert_eq!(expected, "D");
+                }
+                ["P", key] => match values.get(key) {
+                    Some(value) => {
+                        assert_eq!(expected, format!("P found {value}"));
+                        found += 1;
+                    }
+                    None => assert_eq!(expected, "P missing"),
+                },
+                _ => panic!("Unexpected operation {line}"),
+            }
+        }
+        assert!(found >= 900);
+    }
+}
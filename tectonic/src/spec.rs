@@ -1,11 +1,10 @@
 #![allow(clippy::needless_return)]
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use rand::{Rng, SeedableRng};
 
 use crate::keyset::Key;
 use rand::distr::weighted::WeightedIndex;
-use rand::distr::{Alphabetic, Alphanumeric};
 use rand_distr::Distribution as _;
 use rand_xoshiro::Xoshiro256Plus;
 use schemars::JsonSchema;
@@ -15,13 +14,44 @@ use statrs::function::gamma::gamma;
 use statrs::function::harmonic::gen_harmonic;
 use std::borrow::Cow;
 use std::io::Write;
+use std::str::FromStr;
 
-struct Numeric;
-impl rand::distr::Distribution<u8> for Numeric {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u8 {
-        const RANGE: u8 = 10;
-        rng.random_range(0..RANGE) + b'0'
+const ALPHANUMERIC: &[u8; 62] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+const ALPHABETIC: &[u8; 52] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const NUMERIC: &[u8; 10] = b"0123456789";
+
+/// The most characters sampled at once.
+const CHAR_BLOCK_BYTES: usize = 256;
+
+/// Samples `len` characters uniformly from `chars`, passing them to `sink` in blocks.
+///
+/// Random bytes are drawn a block at a time and mapped to characters with a table lookup. Bytes
+/// past the largest multiple of `N` are rejected, so that every character is equally likely. The
+/// rejection does not branch, so that the loop stays cheap.
+fn sample_chars<const N: usize>(
+    rng: &mut impl Rng,
+    chars: &[u8; N],
+    len: usize,
+    mut sink: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let limit = 256 - 256 % N;
+    let mut random = [0u8; CHAR_BLOCK_BYTES];
+    let mut block = [0u8; CHAR_BLOCK_BYTES];
+    let mut remaining = len;
+    while remaining > 0 {
+        // Draws a few more bytes than needed, to make up for the rejected ones.
+        let random = &mut random[..(remaining + remaining / 16 + 8).min(CHAR_BLOCK_BYTES)];
+        rng.fill_bytes(random);
+        let mut accepted = 0;
+        for &byte in random.iter() {
+            block[accepted % CHAR_BLOCK_BYTES] = chars[byte as usize % N];
+            accepted += ((byte as usize) < limit) as usize;
+        }
+        let n = accepted.min(remaining);
+        sink(&block[..n])?;
+        remaining -= n;
     }
+    return Ok(());
 }
 
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
@@ -204,6 +234,15 @@ impl Distribution {
         };
     }
 
+    /// Multiplies `n` by `scale` if this is a Zipf distribution.
+    pub fn scale_zipf_n(&mut self, scale: f64) -> Result<()> {
+        if let Self::Zipf { n, s, distr } = self {
+            *n = ((*n as f64 * scale).round() as usize).max(1);
+            *distr = rand_distr::Zipf::new(*n as f64, *s)?;
+        }
+        return Ok(());
+    }
+
     pub fn default_key_selection() -> Self {
         let min = 0.;
         let max = 1.;
@@ -216,6 +255,83 @@ impl Distribution {
     }
 }
 
+#[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum RankDistributionConfig {
+    ZipfRank { s: f64 },
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(try_from = "RankDistributionConfig")]
+/// Distributions over the rank of a valid key. Their domain is the number of valid keys at the time
+/// a key is selected.
+pub enum RankDistribution {
+    /// Zipf distribution with exponent `s` over the ranks `1..=n`, where `n` is the number of valid
+    /// keys.
+    ZipfRank { s: f64 },
+}
+
+impl TryFrom<RankDistributionConfig> for RankDistribution {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RankDistributionConfig) -> Result<Self, Self::Error> {
+        use RankDistributionConfig as RC;
+        return match value {
+            RC::ZipfRank { s } => {
+                if s.is_nan() || s < 0. {
+                    bail!("zipf_rank exponent s must be non-negative, got {s}");
+                }
+                Ok(Self::ZipfRank { s })
+            }
+        };
+    }
+}
+
+impl JsonSchema for RankDistribution {
+    fn schema_name() -> Cow<'static, str> {
+        "RankDistribution".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        return RankDistributionConfig::json_schema(generator);
+    }
+}
+
+#[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
+#[serde(untagged)]
+/// How a key is selected from the valid keys.
+pub enum Selection {
+    /// A distribution whose samples are clamped to [0, 1) and scaled to the number of valid keys.
+    Distribution(Distribution),
+    /// A distribution over key ranks, whose domain tracks the number of valid keys.
+    Rank(RankDistribution),
+}
+
+impl Selection {
+    /// Samples a position in `[0, len)`.
+    pub fn sample_position(&self, rng: &mut impl Rng, len: usize) -> usize {
+        return match self {
+            Self::Distribution(distr) => {
+                let x = distr.evaluate(rng).clamp(0., 1. - f64::EPSILON);
+                (x * len as f64) as usize
+            }
+            Self::Rank(RankDistribution::ZipfRank { s }) => {
+                if len == 0 {
+                    return 0;
+                }
+                let distr = rand_distr::Zipf::new(len as f64, *s)
+                    .expect("to be able to construct a zipf distribution over the valid keys");
+                let rank = distr.sample(rng) as usize;
+                rank.clamp(1, len) - 1
+            }
+        };
+    }
+
+    pub fn default_key_selection() -> Self {
+        return Self::Distribution(Distribution::default_key_selection());
+    }
+}
+
 // No docstring
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 #[serde(untagged)]
@@ -298,7 +414,7 @@ pub enum StringExprInner {
         len: usize,
         amount: usize,
         probability: f64,
-        hot_ranges: Vec<Key>,
+        hot_ranges: Vec<Box<[u8]>>,
     },
 }
 
@@ -348,13 +464,11 @@ impl TryFrom<StringExprInnerConfig> for StringExprInner {
                 amount,
                 probability,
             } => {
-                let mut rng = Xoshiro256Plus::from_os_rng();
-                let rng_ref = &mut rng;
-                let mut hot_ranges = Vec::with_capacity(amount);
-                for _ in 0..amount {
-                    let key: Key = rng_ref.sample_iter(Alphanumeric).take(len).collect();
-                    hot_ranges.push(key);
-                }
+                // Placeholders until `WorkloadSpec::apply_seed` draws them from the workload seed.
+                let mut rng = Xoshiro256Plus::seed_from_u64(0);
+                let hot_ranges = (0..amount)
+                    .map(|_| alphanumeric_key(&mut rng, len))
+                    .collect();
                 Ok(Self::HotRange {
                     len,
                     amount,
@@ -375,7 +489,141 @@ pub enum RangeFormat {
     StartEnd,
 }
 
+impl FromStr for RangeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        return match s {
+            "StartCount" => Ok(Self::StartCount),
+            "StartEnd" => Ok(Self::StartEnd),
+            _ => bail!("Unknown range format {s}, expected StartCount or StartEnd"),
+        };
+    }
+}
+
+#[derive(serde::Deserialize, JsonSchema, Copy, Clone, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+/// The order of the valid keys that a key selection strategy is applied to.
+pub enum SelectionOrder {
+    /// Selected positions are scattered over the valid keys with a hash, so skew does not follow
+    /// insertion or key order.
+    #[default]
+    Hashed,
+    /// Selected positions index the valid keys in the order they were inserted. Deletes and range
+    /// operations that sort the valid keys will reorder them.
+    Insertion,
+    /// Selected positions index the valid keys in lexicographic order, e.g. a selection skewed
+    /// towards 0 will mostly hit the smallest keys.
+    Sorted,
+}
+
+/// A random alphanumeric key, as used by hot ranges.
+fn alphanumeric_key(rng: &mut impl Rng, len: usize) -> Box<[u8]> {
+    let mut key = vec![0; len];
+    CharacterSet::Alphanumeric.fill(rng, &mut key);
+    return key.into_boxed_slice();
+}
+
 impl StringExpr {
+    /// Multiplies the `amount` of every hot range in the expression by `scale`, generating or
+    /// dropping hot ranges to match. Like the parsed ones, the generated hot ranges are
+    /// placeholders until `WorkloadSpec::apply_seed`.
+    pub fn scale_hot_range_amount(&mut self, scale: f64) {
+        let Self::Inner(inner) = self else {
+            return;
+        };
+        match inner {
+            StringExprInner::Uniform { .. } => (),
+            StringExprInner::Weighted { items, .. } => {
+                for item in items {
+                    item.value.scale_hot_range_amount(scale);
+                }
+            }
+            StringExprInner::Segmented { segments, .. } => {
+                for segment in segments {
+                    segment.scale_hot_range_amount(scale);
+                }
+            }
+            StringExprInner::HotRange {
+                len,
+                amount,
+                hot_ranges,
+                ..
+            } => {
+                *amount = ((*amount as f64 * scale).round() as usize).max(1);
+                let mut rng = Xoshiro256Plus::seed_from_u64(0);
+                hot_ranges.truncate(*amount);
+                while hot_ranges.len() < *amount {
+                    hot_ranges.push(alphanumeric_key(&mut rng, *len));
+                }
+            }
+        }
+    }
+
+    /// Regenerates every hot range in the expression from `rng`, so that they only depend on the
+    /// seed of the workload.
+    pub fn seed_hot_ranges(&mut self, rng: &mut impl Rng) {
+        let Self::Inner(inner) = self else {
+            return;
+        };
+        match inner {
+            StringExprInner::Uniform { .. } => (),
+            StringExprInner::Weighted { items, .. } => {
+                for item in items {
+                    item.value.seed_hot_ranges(rng);
+                }
+            }
+            StringExprInner::Segmented { segments, .. } => {
+                for segment in segments {
+                    segment.seed_hot_ranges(rng);
+                }
+            }
+            StringExprInner::HotRange {
+                len, hot_ranges, ..
+            } => {
+                for hot_range_key in hot_ranges {
+                    *hot_range_key = alphanumeric_key(rng, *len);
+                }
+            }
+        }
+    }
+
+    /// The number of distinct strings the expression can generate, or `None` if it is unbounded or
+    /// unknown. Weighted and segmented expressions give an upper bound.
+    pub fn cardinality(&self, character_set_parent: Option<CharacterSet>) -> Option<f64> {
+        let Self::Inner(inner) = self else {
+            return Some(1.);
+        };
+        return match inner {
+            StringExprInner::Uniform { len, character_set } => {
+                let character_set = character_set.or(character_set_parent).unwrap_or_default();
+                let size = character_set.size();
+                // Lengths are truncated, so a length sampled from [min, max) is one of these.
+                let (min_len, max_len) = match len {
+                    NumberExpr::Constant(len) => (*len, *len),
+                    NumberExpr::Sampled(Distribution::Uniform { min, max, .. }) => {
+                        (*min, (*max - 1.).max(*min))
+                    }
+                    NumberExpr::Sampled(_) => return None,
+                };
+                let lengths = min_len.max(0.).floor() as i32..=max_len.max(0.).floor() as i32;
+                Some(lengths.map(|len| size.powi(len)).sum())
+            }
+            StringExprInner::Weighted { items, .. } => items
+                .iter()
+                .filter(|item| item.weight > 0.)
+                .map(|item| item.value.cardinality(None))
+                .sum(),
+            StringExprInner::Segmented { segments, .. } => segments
+                .iter()
+                .map(|segment| segment.cardinality(None))
+                .product(),
+            StringExprInner::HotRange { len, .. } => {
+                Some(CharacterSet::Alphanumeric.size().powi(*len as i32))
+            }
+        };
+    }
+
     pub fn generate(&self, rng: &mut impl Rng, character_set_parent: Option<CharacterSet>) -> Key {
         return match self {
             Self::Constant(val) => Key::from(val.as_bytes()),
@@ -390,17 +638,9 @@ impl StringExpr {
                         let character_set =
                             character_set.or(character_set_parent).unwrap_or_default();
                         let len = length.evaluate(rng) as usize;
-                        match character_set {
-                            CharacterSet::Alphanumeric => {
-                                Key::from_iter(rng.sample_iter(Alphanumeric).take(len))
-                            }
-                            CharacterSet::Alphabetic => {
-                                Key::from_iter(rng.sample_iter(Alphabetic).take(len))
-                            }
-                            CharacterSet::Numeric => {
-                                Key::from_iter(rng.sample_iter(Numeric).take(len))
-                            }
-                        }
+                        let mut key = vec![0; len];
+                        character_set.fill(rng, &mut key);
+                        Key::from(key)
                     }
                     S::Weighted { items, distr } => {
                         let random_value = rng.sample(distr);
@@ -432,10 +672,9 @@ impl StringExpr {
                         let is_hot = rng.random_bool(*probability);
                         return if is_hot {
                             let index = rng.random_range(0..hot_ranges.len());
-                            hot_ranges[index].clone()
+                            Key::from(hot_ranges[index].as_ref())
                         } else {
-                            let key: Key = rng.sample_iter(Alphanumeric).take(*len).collect();
-                            Key::from(key)
+                            Key::from(alphanumeric_key(rng, *len))
                         };
                     }
                 }
@@ -464,22 +703,7 @@ impl StringExpr {
                         let character_set =
                             character_set.or(character_set_parent).unwrap_or_default();
                         let len = length.evaluate(rng) as usize;
-                        fn write_all(
-                            writer: &mut impl Write,
-                            rng: &mut impl Rng,
-                            distr: impl rand::distr::Distribution<u8>,
-                            len: usize,
-                        ) -> Result<()> {
-                            for ch in rng.sample_iter(distr).take(len) {
-                                writer.write_all(&[ch]).context("Writing sampled string")?;
-                            }
-                            Ok(())
-                        }
-                        return match character_set {
-                            CharacterSet::Alphanumeric => write_all(writer, rng, Alphanumeric, len),
-                            CharacterSet::Alphabetic => write_all(writer, rng, Alphabetic, len),
-                            CharacterSet::Numeric => write_all(writer, rng, Numeric, len),
-                        };
+                        return character_set.write_all(writer, rng, len);
                     }
                     S::Weighted { items, distr } => {
                         let random_value = rng.sample(distr);
@@ -510,14 +734,14 @@ impl StringExpr {
                         ..
                     } => {
                         let is_hot = rng.random_bool(*probability);
-                        let key = if is_hot {
+                        if is_hot {
                             let index = rng.random_range(0..hot_ranges.len());
-                            hot_ranges[index].clone()
+                            writer
+                                .write_all(&hot_ranges[index])
+                                .context("Writing weighted string")
                         } else {
-                            let key: Key = rng.sample_iter(Alphanumeric).take(*len).collect();
-                            Key::from(key)
-                        };
-                        writer.write_all(&key).context("Writing weighted string")
+                            CharacterSet::Alphanumeric.write_all(writer, rng, *len)
+                        }
                     }
                 }
             }
@@ -527,27 +751,107 @@ impl StringExpr {
 
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Inserts specification.
-pub struct Inserts {
-    /// Number of inserts
-    pub op_count: NumberExpr,
+struct InsertsConfig {
+    /// Number of inserts. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    ratio: Option<f64>,
     /// Key
-    pub key: StringExpr,
+    key: StringExpr,
     /// Value
-    pub val: StringExpr,
+    val: StringExpr,
+    #[serde(default)]
+    character_set: Option<CharacterSet>,
+    /// Whether every insert uses a key that is not already valid, instead of overwriting it.
+    ///
+    /// Keys are checked even if the section sets `skip_key_contains_check`. Generation fails if no
+    /// fresh key is found, e.g. because the keyspace of `key` is exhausted. Cannot be combined
+    /// with `overwrite_probability`.
     #[serde(default)]
+    unique: bool,
+    /// Probability, in [0, 1], that an insert re-inserts a valid key, chosen with
+    /// `overwrite_selection`, instead of creating a new key.
+    #[serde(default)]
+    overwrite_probability: f64,
+    /// Key selection strategy for overwrites
+    #[serde(default = "Selection::default_key_selection")]
+    overwrite_selection: Selection,
+    /// The order of the valid keys that `overwrite_selection` is applied to
+    #[serde(default)]
+    overwrite_selection_order: SelectionOrder,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(try_from = "InsertsConfig")]
+/// Inserts specification.
+pub struct Inserts {
+    pub op_count: Option<NumberExpr>,
+    pub ratio: Option<f64>,
+    pub key: StringExpr,
+    pub val: StringExpr,
     pub character_set: Option<CharacterSet>,
+    /// Whether every insert uses a key that is not already valid.
+    pub unique: bool,
+    /// Probability that an insert re-inserts a valid key instead of creating a new key.
+    pub overwrite_probability: f64,
+    pub overwrite_selection: Selection,
+    pub overwrite_selection_order: SelectionOrder,
+}
+
+impl TryFrom<InsertsConfig> for Inserts {
+    type Error = anyhow::Error;
+
+    fn try_from(value: InsertsConfig) -> Result<Self, Self::Error> {
+        let probability = value.overwrite_probability;
+        if !(0. ..=1.).contains(&probability) {
+            bail!("overwrite_probability must be in [0, 1], got {probability}");
+        }
+        if value.unique && probability > 0. {
+            bail!("Inserts cannot be unique and overwrite keys with overwrite_probability");
+        }
+        return Ok(Self {
+            op_count: value.op_count,
+            ratio: value.ratio,
+            key: value.key,
+            val: value.val,
+            character_set: value.character_set,
+            unique: value.unique,
+            overwrite_probability: value.overwrite_probability,
+            overwrite_selection: value.overwrite_selection,
+            overwrite_selection_order: value.overwrite_selection_order,
+        });
+    }
+}
+
+impl JsonSchema for Inserts {
+    fn schema_name() -> Cow<'static, str> {
+        "Inserts".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        return InsertsConfig::json_schema(generator);
+    }
 }
 
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Updates specification.
 pub struct Updates {
-    /// Number of updates
-    pub op_count: NumberExpr,
+    /// Number of updates. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Value
     pub val: StringExpr,
     /// Key selection strategy
-    #[serde(default = "Distribution::default_key_selection")]
-    pub selection: Distribution,
+    #[serde(default = "Selection::default_key_selection")]
+    pub selection: Selection,
+    /// The order of the valid keys that `selection` is applied to
+    #[serde(default)]
+    pub selection_order: SelectionOrder,
     ///// Key sort order
     //pub sort_by: SortBy,
     #[serde(default)]
@@ -557,13 +861,20 @@ pub struct Updates {
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Merges (read-modify-write) specification.
 pub struct Merges {
-    /// Number of merges
-    pub op_count: NumberExpr,
+    /// Number of merges. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Value
     pub val: StringExpr,
     /// Key selection strategy
-    #[serde(default = "Distribution::default_key_selection")]
-    pub selection: Distribution,
+    #[serde(default = "Selection::default_key_selection")]
+    pub selection: Selection,
+    /// The order of the valid keys that `selection` is applied to
+    #[serde(default)]
+    pub selection_order: SelectionOrder,
     ///// Key sort order
     //pub sort_by: SortBy,
     #[serde(default)]
@@ -573,11 +884,18 @@ pub struct Merges {
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Non-empty point deletes specification.
 pub struct PointDeletes {
-    /// Number of non-empty point deletes
-    pub op_count: NumberExpr,
+    /// Number of non-empty point deletes. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Key selection strategy
-    #[serde(default = "Distribution::default_key_selection")]
-    pub selection: Distribution,
+    #[serde(default = "Selection::default_key_selection")]
+    pub selection: Selection,
+    /// The order of the valid keys that `selection` is applied to
+    #[serde(default)]
+    pub selection_order: SelectionOrder,
     ///// Key sort order
     //pub sort_by: SortBy,
 }
@@ -585,8 +903,12 @@ pub struct PointDeletes {
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Empty point deletes specification.
 pub struct EmptyPointDeletes {
-    /// Number of empty point deletes
-    pub op_count: NumberExpr,
+    /// Number of empty point deletes. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Key
     pub key: StringExpr,
     #[serde(default)]
@@ -595,13 +917,21 @@ pub struct EmptyPointDeletes {
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Range deletes specification.
 pub struct RangeDeletes {
-    /// Number of range deletes
-    pub op_count: NumberExpr,
+    /// Number of range deletes. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Selectivity of range deletes. Based off of the range of valid keys, not the full key space.
     pub selectivity: NumberExpr,
     /// Key selection strategy of the start key
-    #[serde(default = "Distribution::default_key_selection")]
-    pub selection: Distribution,
+    #[serde(default = "Selection::default_key_selection")]
+    pub selection: Selection,
+    /// The order of the valid keys that `selection` is applied to. Defaults to `hashed` for
+    /// `StartCount` ranges and `sorted` for `StartEnd` ranges.
+    #[serde(default)]
+    pub selection_order: Option<SelectionOrder>,
     /// The format for the range
     #[serde(default)]
     pub range_format: RangeFormat,
@@ -614,11 +944,18 @@ pub struct RangeDeletes {
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Non-empty point queries specification.
 pub struct PointQueries {
-    /// Number of point queries
-    pub op_count: NumberExpr,
+    /// Number of point queries. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Key selection strategy of the start key
-    #[serde(default = "Distribution::default_key_selection")]
-    pub selection: Distribution,
+    #[serde(default = "Selection::default_key_selection")]
+    pub selection: Selection,
+    /// The order of the valid keys that `selection` is applied to
+    #[serde(default)]
+    pub selection_order: SelectionOrder,
     ///// Key sort order
     //pub sort_by: SortBy,
 }
@@ -626,8 +963,12 @@ pub struct PointQueries {
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Empty point queries specification.
 pub struct EmptyPointQueries {
-    /// Number of point queries
-    pub op_count: NumberExpr,
+    /// Number of point queries. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Key
     pub key: StringExpr,
     #[serde(default)]
@@ -637,13 +978,21 @@ pub struct EmptyPointQueries {
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 /// Range queries specification.
 pub struct RangeQueries {
-    /// Number of range queries
-    pub op_count: NumberExpr,
+    /// Number of range queries. Mutually exclusive with `ratio`.
+    #[serde(default)]
+    pub op_count: Option<NumberExpr>,
+    /// Share of the group's `total_ops`. Mutually exclusive with `op_count`.
+    #[serde(default)]
+    pub ratio: Option<f64>,
     /// Selectivity of range queries. Based off of the range of valid keys, not the full key-space.
     pub selectivity: NumberExpr,
     /// Key selection strategy of the start key
-    #[serde(default = "Distribution::default_key_selection")]
-    pub selection: Distribution,
+    #[serde(default = "Selection::default_key_selection")]
+    pub selection: Selection,
+    /// The order of the valid keys that `selection` is applied to. Defaults to `hashed` for
+    /// `StartCount` ranges and `sorted` for `StartEnd` ranges.
+    #[serde(default)]
+    pub selection_order: Option<SelectionOrder>,
     /// The format for the range
     #[serde(default)]
     pub range_format: RangeFormat,
@@ -663,6 +1012,12 @@ pub struct Sorted {
 
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
 pub struct WorkloadSpecGroup {
+    /// A label for the group, e.g. `load` or `run`, written in phase markers.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Total number of operations in the group, shared between operations by their `ratio`.
+    #[serde(default)]
+    pub total_ops: Option<usize>,
     pub sorted: Option<Sorted>,
     pub inserts: Option<Inserts>,
     pub updates: Option<Updates>,
@@ -675,6 +1030,339 @@ pub struct WorkloadSpecGroup {
     pub range_queries: Option<RangeQueries>,
     #[serde(default)]
     pub character_set: Option<CharacterSet>,
+    /// How the operations of the group are laid out over time.
+    #[serde(default)]
+    pub ordering: GroupOrdering,
+}
+
+fn default_ramp_strength() -> f64 {
+    return 1.0;
+}
+
+#[derive(serde::Deserialize, JsonSchema, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// How the operations of a group are laid out over time.
+///
+/// Writes are inserts, updates, merges and deletes. Reads are point and range queries.
+enum GroupOrderingConfig {
+    /// Operations are shuffled, so the operation mix is the same across the whole group.
+    Shuffled,
+    /// Operations are emitted one type at a time, in the order inserts, updates, merges, point
+    /// deletes, empty point deletes, range deletes, point queries, empty point queries and range
+    /// queries.
+    Sequential,
+    /// The operation mix shifts linearly from reads to writes over the group.
+    Ramp {
+        /// How steep the ramp is, in [-1, 1]. At 1 the share of writes goes from 0 at the start of
+        /// the group to its maximum at the end. With as many reads as writes, 0.8 goes from 90%
+        /// reads to 90% writes. Negative values ramp from writes to reads.
+        #[serde(default = "default_ramp_strength")]
+        strength: f64,
+    },
+    /// Writes are emitted in bursts that take up the first `duty` fraction of every `period`
+    /// operations. Reads are spread over the whole group.
+    Bursty {
+        /// The number of operations from the start of one burst to the start of the next.
+        period: usize,
+        /// The fraction of each period, in (0, 1], that the burst takes up.
+        duty: f64,
+    },
+    /// The operation mix changes at operation `at` of the group.
+    Step {
+        /// The operation at which the mix changes.
+        at: usize,
+        /// The share of writes, in [0, 1], among the operations before `at`. The remaining
+        /// operations of the group come after `at`, so their mix follows from the counts of the
+        /// group. The share is moved as little as needed to fit those counts.
+        writes_before: f64,
+    },
+}
+
+#[derive(serde::Deserialize, Copy, Clone, Debug, Default)]
+#[serde(try_from = "GroupOrderingConfig")]
+/// How the operations of a group are laid out over time.
+pub enum GroupOrdering {
+    #[default]
+    Shuffled,
+    Sequential,
+    /// The share of writes changes linearly, with `strength` in [-1, 1].
+    Ramp {
+        strength: f64,
+    },
+    /// Writes take up the first `duty` fraction, in (0, 1], of every `period` operations.
+    Bursty {
+        period: usize,
+        duty: f64,
+    },
+    /// A share `writes_before`, in [0, 1], of the operations before operation `at` are writes.
+    Step {
+        at: usize,
+        writes_before: f64,
+    },
+}
+
+impl TryFrom<GroupOrderingConfig> for GroupOrdering {
+    type Error = anyhow::Error;
+
+    fn try_from(value: GroupOrderingConfig) -> Result<Self, Self::Error> {
+        use GroupOrderingConfig as OC;
+        return match value {
+            OC::Shuffled => Ok(Self::Shuffled),
+            OC::Sequential => Ok(Self::Sequential),
+            OC::Ramp { strength } => {
+                if !(-1.0..=1.0).contains(&strength) {
+                    bail!("ramp strength must be in [-1, 1], got {strength}");
+                }
+                Ok(Self::Ramp { strength })
+            }
+            OC::Bursty { period, duty } => {
+                if period == 0 {
+                    bail!("bursty period must be greater than 0");
+                }
+                if !(duty > 0.0 && duty <= 1.0) {
+                    bail!("bursty duty must be in (0, 1], got {duty}");
+                }
+                Ok(Self::Bursty { period, duty })
+            }
+            OC::Step { at, writes_before } => {
+                if !(0.0..=1.0).contains(&writes_before) {
+                    bail!("step writes_before must be in [0, 1], got {writes_before}");
+                }
+                Ok(Self::Step { at, writes_before })
+            }
+        };
+    }
+}
+
+impl JsonSchema for GroupOrdering {
+    fn schema_name() -> Cow<'static, str> {
+        "GroupOrdering".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        return GroupOrderingConfig::json_schema(generator);
+    }
+}
+
+/// The types of operations of a group.
+#[derive(Debug, Copy, Clone, Eq, Ord, PartialOrd, PartialEq)]
+pub(crate) enum Op {
+    Insert,
+    Update,
+    Merge,
+    PointDelete,
+    PointDeleteEmpty,
+    RangeDelete,
+    PointQuery,
+    EmptyPointQuery,
+    RangeQuery,
+}
+
+impl Op {
+    /// All operations, in the order kept for each enum/match statement.
+    pub(crate) const ALL: [Op; 9] = [
+        Op::Insert,
+        Op::Update,
+        Op::Merge,
+        Op::PointDelete,
+        Op::PointDeleteEmpty,
+        Op::RangeDelete,
+        Op::PointQuery,
+        Op::EmptyPointQuery,
+        Op::RangeQuery,
+    ];
+
+    /// The field of the operation in a group of the spec, e.g. `point_queries`.
+    pub(crate) fn name(&self) -> &'static str {
+        return match self {
+            Op::Insert => "inserts",
+            Op::Update => "updates",
+            Op::Merge => "merges",
+            Op::PointDelete => "point_deletes",
+            Op::PointDeleteEmpty => "empty_point_deletes",
+            Op::RangeDelete => "range_deletes",
+            Op::PointQuery => "point_queries",
+            Op::EmptyPointQuery => "empty_point_queries",
+            Op::RangeQuery => "range_queries",
+        };
+    }
+
+    pub(crate) fn is_write(&self) -> bool {
+        return match self {
+            Op::Insert
+            | Op::Update
+            | Op::Merge
+            | Op::PointDelete
+            | Op::PointDeleteEmpty
+            | Op::RangeDelete => true,
+            Op::PointQuery | Op::EmptyPointQuery | Op::RangeQuery => false,
+        };
+    }
+}
+
+impl WorkloadSpecGroup {
+    /// The `op_count` and `ratio` of an operation type, if the group has that operation.
+    fn op_amount(&self, op: Op) -> Option<(Option<&NumberExpr>, Option<f64>)> {
+        return match op {
+            Op::Insert => self
+                .inserts
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::Update => self
+                .updates
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::Merge => self.merges.as_ref().map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::PointDelete => self
+                .point_deletes
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::PointDeleteEmpty => self
+                .empty_point_deletes
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::RangeDelete => self
+                .range_deletes
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::PointQuery => self
+                .point_queries
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::EmptyPointQuery => self
+                .empty_point_queries
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+            Op::RangeQuery => self
+                .range_queries
+                .as_ref()
+                .map(|x| (x.op_count.as_ref(), x.ratio)),
+        };
+    }
+
+    fn scale_parameters(&mut self, scale: f64) -> Result<()> {
+        let selections = [
+            self.updates.as_mut().map(|x| &mut x.selection),
+            self.merges.as_mut().map(|x| &mut x.selection),
+            self.point_deletes.as_mut().map(|x| &mut x.selection),
+            self.range_deletes.as_mut().map(|x| &mut x.selection),
+            self.point_queries.as_mut().map(|x| &mut x.selection),
+            self.range_queries.as_mut().map(|x| &mut x.selection),
+        ];
+        for selection in selections.into_iter().flatten() {
+            if let Selection::Distribution(distr) = selection {
+                distr.scale_zipf_n(scale)?;
+            }
+        }
+
+        for string_expr in self.string_exprs_mut() {
+            string_expr.scale_hot_range_amount(scale);
+        }
+        return Ok(());
+    }
+
+    /// Every key and value expression of the group.
+    fn string_exprs_mut(&mut self) -> impl Iterator<Item = &mut StringExpr> {
+        let (insert_key, insert_val) = self
+            .inserts
+            .as_mut()
+            .map(|x| (&mut x.key, &mut x.val))
+            .unzip();
+        let string_exprs = [
+            insert_key,
+            insert_val,
+            self.updates.as_mut().map(|x| &mut x.val),
+            self.merges.as_mut().map(|x| &mut x.val),
+            self.empty_point_deletes.as_mut().map(|x| &mut x.key),
+            self.empty_point_queries.as_mut().map(|x| &mut x.key),
+        ];
+        return string_exprs.into_iter().flatten();
+    }
+
+    fn ratio_sum(&self) -> f64 {
+        return Op::ALL
+            .iter()
+            .filter_map(|&op| self.op_amount(op).and_then(|(_, ratio)| ratio))
+            .sum();
+    }
+
+    /// The expected number of operations of a type in the group.
+    pub(crate) fn expected_op_count(&self, op: Op) -> f64 {
+        return match self.op_amount(op) {
+            Some((Some(op_count), _)) => op_count.expected_value(),
+            Some((None, Some(ratio))) => {
+                let total_ops = self.total_ops.unwrap_or(0) as f64;
+                total_ops * ratio / self.ratio_sum()
+            }
+            _ => 0.,
+        };
+    }
+
+    /// Evaluates the number of operations of each type in the group, in the order of `Op::ALL`.
+    /// Every count, or `total_ops`, is multiplied by `scale`.
+    ///
+    /// Ratios are converted to counts with the largest remainder method, so the counts always add
+    /// up to `total_ops`. Ties go to the operation that comes first in `Op::ALL`.
+    pub(crate) fn op_counts(
+        &self,
+        rng: &mut impl Rng,
+        scale: f64,
+    ) -> Result<[usize; Op::ALL.len()]> {
+        let mut counts = [0; Op::ALL.len()];
+        let mut ratios = [0.; Op::ALL.len()];
+        let mut has_ratio = false;
+        for (i, &op) in Op::ALL.iter().enumerate() {
+            match self.op_amount(op) {
+                None => (),
+                Some((Some(_), Some(_))) => {
+                    bail!("{op:?} operations cannot have both an `op_count` and a `ratio`")
+                }
+                Some((None, None)) => bail!("{op:?} operations need an `op_count` or a `ratio`"),
+                Some((Some(op_count), None)) => {
+                    if self.total_ops.is_some() {
+                        bail!(
+                            "{op:?} operations must use a `ratio` because the group has `total_ops`"
+                        );
+                    }
+                    counts[i] = (op_count.evaluate(rng) * scale) as usize;
+                }
+                Some((None, Some(ratio))) => {
+                    if self.total_ops.is_none() {
+                        bail!("{op:?} operations have a `ratio` but the group has no `total_ops`");
+                    }
+                    if !ratio.is_finite() || ratio < 0. {
+                        bail!("{op:?} operations have an invalid `ratio` of {ratio}");
+                    }
+                    ratios[i] = ratio;
+                    has_ratio = true;
+                }
+            }
+        }
+
+        let Some(total_ops) = self.total_ops else {
+            return Ok(counts);
+        };
+        let total_ops = (total_ops as f64 * scale).round() as usize;
+        let ratio_sum: f64 = ratios.iter().sum();
+        if !has_ratio || ratio_sum <= 0. {
+            bail!("A group with `total_ops` needs operations with a positive `ratio`");
+        }
+
+        let exact = ratios.map(|ratio| total_ops as f64 * ratio / ratio_sum);
+        for (count, exact) in counts.iter_mut().zip(exact) {
+            *count = exact.floor() as usize;
+        }
+        let mut by_remainder: Vec<usize> = (0..Op::ALL.len()).collect();
+        by_remainder.sort_by(|&a, &b| {
+            (exact[b] - exact[b].floor()).total_cmp(&(exact[a] - exact[a].floor()))
+        });
+        let remaining = total_ops.saturating_sub(counts.iter().sum());
+        for &i in by_remainder.iter().take(remaining) {
+            counts[i] += 1;
+        }
+
+        return Ok(counts);
+    }
 }
 
 #[derive(serde::Deserialize, JsonSchema, Default, Copy, Clone, Debug)]
@@ -692,107 +1380,389 @@ pub enum CharacterSet {
     // Utf8,
 }
 
+impl CharacterSet {
+    /// Samples `len` characters uniformly from the set, passing them to `sink` in blocks.
+    fn sample(
+        &self,
+        rng: &mut impl Rng,
+        len: usize,
+        sink: impl FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        return match self {
+            Self::Alphanumeric => sample_chars(rng, ALPHANUMERIC, len, sink),
+            Self::Alphabetic => sample_chars(rng, ALPHABETIC, len, sink),
+            Self::Numeric => sample_chars(rng, NUMERIC, len, sink),
+        };
+    }
+
+    /// Fills `out` with characters drawn uniformly from the set.
+    pub fn fill(&self, rng: &mut impl Rng, out: &mut [u8]) {
+        let mut filled = 0;
+        self.sample(rng, out.len(), |block| {
+            out[filled..filled + block.len()].copy_from_slice(block);
+            filled += block.len();
+            return Ok(());
+        })
+        .expect("filling a buffer to not fail");
+    }
+
+    /// Writes `len` characters drawn uniformly from the set.
+    pub fn write_all(&self, writer: &mut impl Write, rng: &mut impl Rng, len: usize) -> Result<()> {
+        return self.sample(rng, len, |block| {
+            return writer.write_all(block).context("Writing sampled string");
+        });
+    }
+
+    /// The number of characters in the set.
+    fn size(&self) -> f64 {
+        return match self {
+            Self::Alphanumeric => 62.,
+            Self::Alphabetic => 52.,
+            Self::Numeric => 10.,
+        };
+    }
+}
+
 #[derive(serde::Deserialize, JsonSchema, Clone, Debug)]
-pub struct WorkloadSpecSection {
+struct WorkloadSpecSectionConfig {
+    /// A label for the section, written in phase markers.
+    #[serde(default)]
+    name: Option<String>,
     /// A list of groups. Groups share valid keys between operations.
     ///
     /// E.g., non-empty point queries will use a key from an insert in this group.
-    pub groups: Vec<WorkloadSpecGroup>,
+    groups: Vec<WorkloadSpecGroup>,
     /// The domain from which the keys will be created from.
     #[serde(default)]
-    pub character_set: Option<CharacterSet>,
+    character_set: Option<CharacterSet>,
     /// Whether to skip the check that a generated key is in the valid key set for inserts and empty point queries/deletes.
     ///
     /// This is useful when the keyspace is much larger than the number of keys being generated, as it can greatly decrease generation time.
     #[serde(default)]
+    skip_key_contains_check: bool,
+    /// Whether the section starts with the valid keys left at the end of the previous section.
+    ///
+    /// `true` inherits all of them, and a number in [0, 1] inherits that fraction of them, chosen at
+    /// random.
+    #[serde(default)]
+    inherit_keys: InheritKeys,
+    /// Whether keys generated in this section are guaranteed to not have been inserted by an
+    /// earlier section. This applies to inserts and to empty point queries/deletes.
+    ///
+    /// A 64 bit hash of every key inserted by earlier sections is kept in memory to check this,
+    /// which takes about 16 bytes per key. Generated keys that collide with them are regenerated
+    /// and reported in the generation summary. Cannot be combined with `inherit_keys`, whose keys
+    /// are valid in this section.
+    #[serde(default)]
+    disjoint_keys: bool,
+    /// Only set on the workload, which has a single key set for all sections. Rejected here rather
+    /// than silently ignored.
+    #[serde(default)]
+    #[schemars(skip)]
+    membership: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(try_from = "WorkloadSpecSectionConfig")]
+pub struct WorkloadSpecSection {
+    pub name: Option<String>,
+    pub groups: Vec<WorkloadSpecGroup>,
+    pub character_set: Option<CharacterSet>,
     pub skip_key_contains_check: bool,
+    pub inherit_keys: InheritKeys,
+    pub disjoint_keys: bool,
+}
+
+impl TryFrom<WorkloadSpecSectionConfig> for WorkloadSpecSection {
+    type Error = anyhow::Error;
+
+    fn try_from(value: WorkloadSpecSectionConfig) -> Result<Self, Self::Error> {
+        if value.disjoint_keys && value.inherit_keys.fraction()? > 0. {
+            bail!("A section with disjoint_keys cannot inherit_keys");
+        }
+        if value.membership.is_some() {
+            bail!("membership can only be set on the workload, not on a section");
+        }
+        return Ok(Self {
+            name: value.name,
+            groups: value.groups,
+            character_set: value.character_set,
+            skip_key_contains_check: value.skip_key_contains_check,
+            inherit_keys: value.inherit_keys,
+            disjoint_keys: value.disjoint_keys,
+        });
+    }
+}
+
+impl JsonSchema for WorkloadSpecSection {
+    fn schema_name() -> Cow<'static, str> {
+        "WorkloadSpecSection".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        return WorkloadSpecSectionConfig::json_schema(generator);
+    }
+}
+
+#[derive(serde::Deserialize, JsonSchema, Copy, Clone, Debug)]
+#[serde(untagged)]
+/// Which of the previous section's valid keys a section starts with.
+pub enum InheritKeys {
+    /// All of the valid keys if `true`, none if `false`.
+    All(bool),
+    /// A fraction, in [0, 1], of the valid keys.
+    Fraction(f64),
+}
+
+impl Default for InheritKeys {
+    fn default() -> Self {
+        return Self::All(false);
+    }
+}
+
+impl InheritKeys {
+    /// The fraction of the previous section's valid keys to inherit.
+    pub fn fraction(&self) -> Result<f64> {
+        return match *self {
+            Self::All(all) => Ok(if all { 1. } else { 0. }),
+            Self::Fraction(fraction) => {
+                if !(0. ..=1.).contains(&fraction) {
+                    bail!("inherit_keys must be a boolean or a fraction in [0, 1], got {fraction}");
+                }
+                Ok(fraction)
+            }
+        };
+    }
 }
 
 impl WorkloadSpecSection {
     fn has_insert(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .inserts
-                .as_ref()
-                .is_some_and(|is| is.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::Insert) > 0.);
     }
     fn has_update(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .updates
-                .as_ref()
-                .is_some_and(|us| us.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::Update) > 0.);
     }
     fn has_merge(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .merges
-                .as_ref()
-                .is_some_and(|ms| ms.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::Merge) > 0.);
     }
     fn has_delete_point(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .point_deletes
-                .as_ref()
-                .is_some_and(|pds| pds.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::PointDelete) > 0.);
     }
     fn has_delete_point_empty(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .empty_point_deletes
-                .as_ref()
-                .is_some_and(|epds| epds.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::PointDeleteEmpty) > 0.);
     }
     fn has_delete_range(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .range_deletes
-                .as_ref()
-                .is_some_and(|rds| rds.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::RangeDelete) > 0.);
     }
     fn has_query_point(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .point_queries
-                .as_ref()
-                .is_some_and(|pqs| pqs.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::PointQuery) > 0.);
     }
     fn has_query_point_empty(&self) -> bool {
-        return self.groups.iter().any(|group| {
-            group
-                .empty_point_queries
-                .as_ref()
-                .is_some_and(|epqs| epqs.op_count.expected_value() > 0.)
-        });
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::EmptyPointQuery) > 0.);
     }
     fn has_query_range(&self) -> bool {
+        return self
+            .groups
+            .iter()
+            .any(|group| group.expected_op_count(Op::RangeQuery) > 0.);
+    }
+    fn has_range_start_end(&self) -> bool {
         return self.groups.iter().any(|group| {
-            group
+            let query_start_end = group
                 .range_queries
                 .as_ref()
-                .is_some_and(|rqs| rqs.op_count.expected_value() > 0.)
+                .is_some_and(|rqs| matches!(rqs.range_format, RangeFormat::StartEnd));
+            let delete_start_end = group
+                .range_deletes
+                .as_ref()
+                .is_some_and(|rds| matches!(rds.range_format, RangeFormat::StartEnd));
+            return (query_start_end && group.expected_op_count(Op::RangeQuery) > 0.)
+                || (delete_start_end && group.expected_op_count(Op::RangeDelete) > 0.);
         });
     }
 }
 
 #[derive(serde::Deserialize, JsonSchema, Debug, Clone)]
-pub struct WorkloadSpec {
-    /// Sections of a workload where a key from one will (probably) not appear in another.
-    pub sections: Vec<WorkloadSpecSection>,
+struct WorkloadSpecConfig {
+    /// Sections of a workload where a key from one will (probably) not appear in another, unless
+    /// the section sets `inherit_keys`. Sections with `disjoint_keys` guarantee it.
+    sections: Vec<WorkloadSpecSection>,
     /// The domain from which the keys will be created from.
     #[serde(default)]
+    character_set: Option<CharacterSet>,
+    /// Multiplies the number of operations of every group, preserving the ratios between them.
+    #[serde(default)]
+    scale: Option<f64>,
+    /// Whether `scale` also multiplies the `n` of Zipf key selections and the `amount` of hot
+    /// ranges.
+    #[serde(default)]
+    scale_parameters: bool,
+    /// How key membership is checked for the whole workload, e.g. whether a generated key for an
+    /// empty point query is valid. By default, a bloom filter is used when it is sufficient.
+    /// `bloom` cannot be combined with non-empty deletes, which a bloom filter cannot forget, or with
+    /// inserts that are not `unique`, which it cannot tell apart from new keys.
+    #[serde(default)]
+    membership: Option<Membership>,
+    /// Seed for the random number generator. The same spec and seed always generate the same
+    /// workload. A random seed is picked, and logged, when it is not set.
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(try_from = "WorkloadSpecConfig")]
+pub struct WorkloadSpec {
+    pub sections: Vec<WorkloadSpecSection>,
     pub character_set: Option<CharacterSet>,
+    pub scale: Option<f64>,
+    pub scale_parameters: bool,
+    pub membership: Option<Membership>,
+    pub seed: Option<u64>,
+}
+
+impl TryFrom<WorkloadSpecConfig> for WorkloadSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(value: WorkloadSpecConfig) -> Result<Self, Self::Error> {
+        let spec = Self {
+            sections: value.sections,
+            character_set: value.character_set,
+            scale: value.scale,
+            scale_parameters: value.scale_parameters,
+            membership: value.membership,
+            seed: value.seed,
+        };
+        if let Some(Membership::Bloom { fp_rate }) = spec.membership {
+            if !(fp_rate > 0. && fp_rate < 1.) {
+                bail!("Bloom filter fp_rate must be in (0, 1), got {fp_rate}");
+            }
+            if spec.has_delete_point() || spec.has_delete_range() {
+                bail!("bloom membership cannot be used with point or range deletes");
+            }
+            if spec.has_non_unique_insert() {
+                bail!(
+                    "bloom membership requires every insert to be unique, because a bloom filter \
+                     cannot tell a repeated key apart"
+                );
+            }
+        }
+        return Ok(spec);
+    }
+}
+
+impl JsonSchema for WorkloadSpec {
+    fn schema_name() -> Cow<'static, str> {
+        "WorkloadSpec".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        return WorkloadSpecConfig::json_schema(generator);
+    }
+}
+
+/// How key membership is checked.
+#[derive(serde::Deserialize, serde::Serialize, JsonSchema, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Membership {
+    /// Keys are members exactly while they are valid.
+    Exact,
+    /// A bloom filter with the given false positive rate. Keys are wrongly reported as members at
+    /// that rate, and deleted keys stay members.
+    Bloom { fp_rate: f64 },
 }
 
 impl WorkloadSpec {
+    /// The expected number of operations of the given type over all sections, including `scale`.
+    pub(crate) fn expected_op_count(&self, op: Op) -> Result<f64> {
+        let count: f64 = self
+            .sections
+            .iter()
+            .flat_map(|section| &section.groups)
+            .map(|group| group.expected_op_count(op))
+            .sum();
+        return Ok(count * self.scale()?);
+    }
+
+    /// The factor that operation counts are multiplied by.
+    pub fn scale(&self) -> Result<f64> {
+        let scale = self.scale.unwrap_or(1.);
+        if !scale.is_finite() || scale <= 0. {
+            bail!("Scale must be a positive number, got {scale}");
+        }
+        return Ok(scale);
+    }
+
+    /// Checks that section and group names fit in a phase marker, which is a line of words.
+    pub fn check_phase_names(&self) -> Result<()> {
+        let names = self.sections.iter().flat_map(|section| {
+            std::iter::once(&section.name).chain(section.groups.iter().map(|group| &group.name))
+        });
+        for name in names.flatten() {
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                bail!(
+                    "Section and group names must be non-empty and without whitespace, got {name:?}"
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    /// Multiplies the `n` of Zipf key selections and the `amount` of hot ranges by the scale, if
+    /// `scale_parameters` is set.
+    pub fn apply_parameter_scale(&mut self) -> Result<()> {
+        let scale = self.scale()?;
+        if !self.scale_parameters || scale == 1. {
+            return Ok(());
+        }
+        for section in &mut self.sections {
+            for group in &mut section.groups {
+                group.scale_parameters(scale)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Picks a random seed if none is set, and regenerates the hot ranges from the seed. Returns
+    /// the seed.
+    pub fn apply_seed(&mut self) -> u64 {
+        let seed = *self.seed.get_or_insert_with(rand::random);
+        // A stream apart from the one that generates the operations.
+        let mut rng = Xoshiro256Plus::seed_from_u64(seed);
+        rng.long_jump();
+        for section in &mut self.sections {
+            for group in &mut section.groups {
+                for string_expr in group.string_exprs_mut() {
+                    string_expr.seed_hot_ranges(&mut rng);
+                }
+            }
+        }
+        return seed;
+    }
+
     pub fn has_insert(&self) -> bool {
         return self.sections.iter().any(WorkloadSpecSection::has_insert);
     }
@@ -838,6 +1808,30 @@ impl WorkloadSpec {
             .iter()
             .any(WorkloadSpecSection::has_query_range);
     }
+    /// Whether any range query or range delete is written with a start and an end key.
+    pub fn has_range_start_end(&self) -> bool {
+        return self
+            .sections
+            .iter()
+            .any(WorkloadSpecSection::has_range_start_end);
+    }
+
+    pub fn has_unique_insert(&self) -> bool {
+        return self
+            .sections
+            .iter()
+            .flat_map(|section| &section.groups)
+            .any(|group| group.inserts.as_ref().is_some_and(|is| is.unique));
+    }
+
+    /// Whether an insert may repeat a valid key, either by overwriting it or by generating it again.
+    pub fn has_non_unique_insert(&self) -> bool {
+        return self
+            .sections
+            .iter()
+            .flat_map(|section| &section.groups)
+            .any(|group| group.inserts.as_ref().is_some_and(|is| !is.unique));
+    }
 
     pub fn skip_contains_check_all(&self) -> bool {
         return self
@@ -0,0 +1,253 @@
+#![allow(clippy::needless_return)]
+
+use crate::compression::{Compression, WorkloadWriter};
+use crate::{OperationWriter, output_stem, workload_extension};
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The phases that start a new output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitBy {
+    Section,
+    Group,
+}
+
+impl FromStr for SplitBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        return match s {
+            "section" => Ok(Self::Section),
+            "group" => Ok(Self::Group),
+            _ => bail!("Unknown phase {s}, expected section or group"),
+        };
+    }
+}
+
+/// How the output is split into multiple files. Without any limit, it is a single file.
+#[derive(Debug, Clone, Default)]
+pub struct SplitOptions {
+    /// The most operations per file.
+    pub ops: Option<usize>,
+    /// The size per file, in uncompressed bytes. A file ends with the operation that reaches it.
+    pub bytes: Option<usize>,
+    /// Starts a new file at every section or group.
+    pub by: Option<SplitBy>,
+}
+
+impl SplitOptions {
+    pub fn is_split(&self) -> bool {
+        return self.ops.is_some() || self.bytes.is_some() || self.by.is_some();
+    }
+}
+
+/// An output file in the manifest of a split workload.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct SplitPart {
+    /// The file name, in the directory of the manifest.
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<usize>,
+    /// The number of operations in the file.
+    pub operations: usize,
+    /// The number of operations in the file by their code, e.g. `I` for inserts.
+    pub op_counts: BTreeMap<char, usize>,
+    /// The uncompressed size of the file, in bytes.
+    pub bytes: usize,
+}
+
+/// The manifest of a split workload, listing its files in order.
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct SplitManifest {
+    pub parts: Vec<SplitPart>,
+}
+
+/// The output file being written.
+struct OpenPart {
+    writer: WorkloadWriter,
+    part: SplitPart,
+    /// The number of operations in the file by the first byte of their line.
+    op_counts: [usize; 128],
+}
+
+/// Writes the operations of a workload into numbered files, e.g. `name.s0.g1.txt`, and a
+/// manifest of them, e.g. `name.manifest.json`.
+///
+/// Operations are counted by their lines, so a file always ends at the end of a line. Lines that
+/// start with `#`, e.g. phase markers, are not operations.
+pub struct SplitWriter {
+    dir: PathBuf,
+    /// The file name up to the extension, e.g. `name` of `name.txt.zst`.
+    stem: String,
+    /// The extension, e.g. `txt.zst` of `name.txt.zst`.
+    extension: String,
+    compression: Compression,
+    options: SplitOptions,
+    section: Option<usize>,
+    group: Option<usize>,
+    /// The index of the next file of the current phase, when splitting by size.
+    part_idx: usize,
+    open: Option<OpenPart>,
+    /// The first byte of the line being written, if it is not at its start.
+    line_code: Option<u8>,
+    manifest: SplitManifest,
+}
+
+impl SplitWriter {
+    /// Splits the workload that would be written to `path` into files next to it.
+    pub fn new(path: &Path, compression: Compression, options: SplitOptions) -> Result<Self> {
+        if options.ops == Some(0) || options.bytes == Some(0) {
+            bail!("Split output files must hold at least one operation or byte");
+        }
+        let (dir, stem) = output_stem(path)?;
+        return Ok(Self {
+            dir,
+            stem,
+            extension: workload_extension(compression),
+            compression,
+            options,
+            section: None,
+            group: None,
+            part_idx: 0,
+            open: None,
+            line_code: None,
+            manifest: SplitManifest::default(),
+        });
+    }
+
+    /// The name of the next file, tagged with its phase and number.
+    fn part_file_name(&self) -> String {
+        let mut name = self.stem.clone();
+        if let Some(section) = self.section {
+            name += &format!(".s{section}");
+        }
+        if let Some(group) = self.group {
+            name += &format!(".g{group}");
+        }
+        if self.options.ops.is_some() || self.options.bytes.is_some() {
+            name += &format!(".{}", self.part_idx);
+        }
+        return format!("{name}.{}", self.extension);
+    }
+
+    fn open_part(&mut self) -> Result<&mut OpenPart> {
+        if self.open.is_none() {
+            let file = self.part_file_name();
+            let writer = WorkloadWriter::create(&self.dir.join(&file), self.compression)?;
+            self.part_idx += 1;
+            self.open = Some(OpenPart {
+                writer,
+                part: SplitPart {
+                    file,
+                    section: self.section,
+                    group: self.group,
+                    operations: 0,
+                    op_counts: BTreeMap::new(),
+                    bytes: 0,
+                },
+                op_counts: [0; 128],
+            });
+        }
+        return Ok(self.open.as_mut().expect("a part to be open"));
+    }
+
+    fn close_part(&mut self) -> Result<()> {
+        if let Some(OpenPart {
+            writer,
+            mut part,
+            op_counts,
+        }) = self.open.take()
+        {
+            writer
+                .finish()
+                .with_context(|| format!("Writing {}", part.file))?;
+            part.op_counts = (0u8..128)
+                .filter(|&code| op_counts[code as usize] > 0)
+                .map(|code| (code as char, op_counts[code as usize]))
+                .collect();
+            self.manifest.parts.push(part);
+        }
+        return Ok(());
+    }
+
+    /// Whether the open file reached one of the size limits.
+    fn is_full(&self, part: &SplitPart) -> bool {
+        return self.options.ops.is_some_and(|ops| part.operations >= ops)
+            || self.options.bytes.is_some_and(|bytes| part.bytes >= bytes);
+    }
+
+    /// Writes the last file and the manifest, returning the manifest.
+    pub fn finish(mut self) -> Result<SplitManifest> {
+        self.close_part()?;
+        let manifest_path = self.dir.join(format!("{}.manifest.json", self.stem));
+        let file = File::create(&manifest_path)
+            .with_context(|| format!("Creating {}", manifest_path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &self.manifest).context("Writing manifest")?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        return Ok(self.manifest);
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let code = *self.line_code.get_or_insert(rest[0]);
+            let open = self.open_part().map_err(std::io::Error::other)?;
+            let (line, is_line_end) = match rest.iter().position(|&b| b == b'\n') {
+                Some(end) => (&rest[..=end], true),
+                None => (rest, false),
+            };
+            open.writer.write_all(line)?;
+            open.part.bytes += line.len();
+            rest = &rest[line.len()..];
+            if !is_line_end {
+                continue;
+            }
+            if code == b'#' {
+                self.line_code = None;
+                continue;
+            }
+            open.part.operations += 1;
+            open.op_counts[(code & 0x7f) as usize] += 1;
+            self.line_code = None;
+            let part = &self.open.as_ref().expect("a part to be open").part;
+            if self.is_full(part) {
+                self.close_part().map_err(std::io::Error::other)?;
+            }
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return match &mut self.open {
+            Some(open) => open.writer.flush(),
+            None => Ok(()),
+        };
+    }
+}
+
+impl OperationWriter for SplitWriter {
+    fn begin_group(&mut self, section: usize, group: usize) -> Result<()> {
+        let (section, group) = match self.options.by {
+            Some(SplitBy::Section) => (Some(section), None),
+            Some(SplitBy::Group) => (Some(section), Some(group)),
+            None => return Ok(()),
+        };
+        if (section, group) != (self.section, self.group) {
+            self.close_part()?;
+            self.section = section;
+            self.group = group;
+            self.part_idx = 0;
+        }
+        return Ok(());
+    }
+}
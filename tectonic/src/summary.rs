@@ -0,0 +1,209 @@
+#![allow(clippy::needless_return)]
+
+use crate::keyset::key_hash;
+use crate::spec::Membership;
+use std::collections::{BTreeMap, HashSet};
+use tracing::info;
+
+/// Statistics about a generated workload, which are also written to `name.summary.json`.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct GenerationSummary {
+    /// The version of tectonic that generated the workload.
+    pub tectonic_version: &'static str,
+    /// The SHA-256 of the workload spec, in hex, when it was generated from its JSON.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spec_sha256: Option<String>,
+    pub seed: u64,
+    /// The `KeySet` implementation that held the valid keys, e.g. `VecKeySet`.
+    pub key_set: &'static str,
+    /// How key membership was checked.
+    pub membership: Membership,
+    /// One entry per section of the workload spec.
+    pub sections: Vec<SectionSummary>,
+    /// The number of distinct keys that operations name, including the bounds of ranges. Only
+    /// counted when the summary is written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distinct_keys: Option<usize>,
+    /// Valid keys at the end of the workload.
+    pub live_keys: usize,
+    /// The lengths of the keys that operations name, including the bounds of ranges.
+    pub key_lengths: LengthHistogram,
+    /// The lengths of the values of inserts, updates and merges.
+    pub value_lengths: LengthHistogram,
+    /// Time spent generating each type of operation, and the whole workload, in seconds.
+    pub timings: BTreeMap<&'static str, f64>,
+}
+
+impl Default for GenerationSummary {
+    fn default() -> Self {
+        return Self {
+            tectonic_version: env!("CARGO_PKG_VERSION"),
+            spec_sha256: None,
+            seed: 0,
+            key_set: "",
+            membership: Membership::Exact,
+            sections: Vec::new(),
+            distinct_keys: None,
+            live_keys: 0,
+            key_lengths: LengthHistogram::default(),
+            value_lengths: LengthHistogram::default(),
+            timings: BTreeMap::new(),
+        };
+    }
+}
+
+/// Statistics about a generated section.
+#[derive(serde::Serialize, Debug, Default, Clone)]
+pub struct SectionSummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// One entry per group of the section.
+    pub groups: Vec<GroupSummary>,
+    /// Generated keys that were discarded because an earlier section used them.
+    pub key_collisions: usize,
+    /// Inserts whose key was already valid, which overwrite the existing value.
+    pub insert_overwrites: usize,
+    /// Valid keys at the end of the section.
+    pub live_keys: usize,
+    /// Approximate memory used by the valid keys at the end of the section, in bytes.
+    pub key_set_memory: usize,
+    /// `key_set_memory` divided by `live_keys`, in bytes.
+    pub key_set_memory_per_key: usize,
+}
+
+/// Statistics about a generated group.
+#[derive(serde::Serialize, Debug, Default, Clone)]
+pub struct GroupSummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The number of generated operations by their field in the spec, e.g. `point_queries`.
+    pub op_counts: BTreeMap<&'static str, usize>,
+}
+
+/// A histogram of lengths in power of two buckets.
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(into = "LengthHistogramJson")]
+pub struct LengthHistogram {
+    count: usize,
+    sum: usize,
+    min: usize,
+    max: usize,
+    /// Bucket `i` counts the lengths in `[2^(i-1), 2^i)`, and bucket 0 the empty ones.
+    buckets: [usize; usize::BITS as usize + 1],
+}
+
+impl Default for LengthHistogram {
+    fn default() -> Self {
+        return Self {
+            count: 0,
+            sum: 0,
+            min: usize::MAX,
+            max: 0,
+            buckets: [0; usize::BITS as usize + 1],
+        };
+    }
+}
+
+impl LengthHistogram {
+    pub fn record(&mut self, len: usize) {
+        self.count += 1;
+        self.sum += len;
+        self.min = self.min.min(len);
+        self.max = self.max.max(len);
+        self.buckets[(usize::BITS - len.leading_zeros()) as usize] += 1;
+    }
+
+    /// Adds the lengths recorded by `other`.
+    pub fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        for (bucket, other) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += other;
+        }
+    }
+}
+
+/// The written form of a `LengthHistogram`, whose buckets are keyed by their smallest length.
+#[derive(serde::Serialize)]
+struct LengthHistogramJson {
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mean: Option<f64>,
+    buckets: BTreeMap<usize, usize>,
+}
+
+impl From<LengthHistogram> for LengthHistogramJson {
+    fn from(histogram: LengthHistogram) -> Self {
+        let has_lengths = histogram.count > 0;
+        return Self {
+            count: histogram.count,
+            min: has_lengths.then_some(histogram.min),
+            max: has_lengths.then_some(histogram.max),
+            mean: has_lengths.then(|| histogram.sum as f64 / histogram.count as f64),
+            buckets: histogram
+                .buckets
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(i, &count)| (if i == 0 { 0 } else { 1 << (i - 1) }, count))
+                .collect(),
+        };
+    }
+}
+
+/// Records the keys that operations name.
+#[derive(Default)]
+pub(crate) struct KeyStats {
+    pub lengths: LengthHistogram,
+    /// The hashes of the keys seen so far, when distinct keys are counted.
+    distinct: Option<HashSet<u64>>,
+}
+
+impl KeyStats {
+    pub fn new(count_distinct: bool) -> Self {
+        return Self {
+            lengths: LengthHistogram::default(),
+            distinct: count_distinct.then(HashSet::new),
+        };
+    }
+
+    pub fn record(&mut self, key: &[u8]) {
+        self.lengths.record(key.len());
+        if let Some(distinct) = &mut self.distinct {
+            // 64 bit hashes keep the memory per key small, and rarely collide.
+            distinct.insert(key_hash(key));
+        }
+    }
+
+    pub fn distinct(&self) -> Option<usize> {
+        return self.distinct.as_ref().map(HashSet::len);
+    }
+}
+
+impl GenerationSummary {
+    pub fn log(&self) {
+        info!(
+            membership = ?self.membership,
+            key_set = self.key_set,
+            live_keys = self.live_keys,
+            "Generation summary"
+        );
+        for (i, section) in self.sections.iter().enumerate() {
+            info!(
+                section = i,
+                key_collisions = section.key_collisions,
+                insert_overwrites = section.insert_overwrites,
+                live_keys = section.live_keys,
+                key_set_memory = section.key_set_memory,
+                key_set_memory_per_key = section.key_set_memory_per_key,
+                "Section summary"
+            );
+        }
+    }
+}
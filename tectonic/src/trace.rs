@@ -0,0 +1,238 @@
+#![allow(clippy::needless_return)]
+
+use crate::spec::RangeFormat;
+use anyhow::{Context, Result, anyhow, bail};
+use std::io::BufRead;
+
+/// The end of a range, as its end key or the number of keys it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeEnd<'a> {
+    Key(&'a [u8]),
+    Count(usize),
+}
+
+/// An operation of a trace, borrowed from the line it was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp<'a> {
+    Insert { key: &'a [u8], value: &'a [u8] },
+    Update { key: &'a [u8], value: &'a [u8] },
+    Merge { key: &'a [u8], value: &'a [u8] },
+    PointDelete { key: &'a [u8] },
+    RangeDelete { start: &'a [u8], end: RangeEnd<'a> },
+    PointQuery { key: &'a [u8] },
+    RangeQuery { start: &'a [u8], end: RangeEnd<'a> },
+}
+
+/// Reads the operations of a trace in the ASCII format that tectonic writes, one per line, e.g.
+/// `I key value` or `S start end`. Lines that start with `#`, e.g. phase markers, and empty lines
+/// are skipped.
+///
+/// Whether a range ends with a key or a count is not written in the trace. Without a
+/// `RangeFormat`, an end of only digits is a count, unless the start is only digits as well.
+pub struct TraceReader<R: BufRead> {
+    reader: R,
+    range_format: Option<RangeFormat>,
+    line: Vec<u8>,
+    line_number: usize,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(reader: R, range_format: Option<RangeFormat>) -> Self {
+        return Self {
+            reader,
+            range_format,
+            line: Vec::new(),
+            line_number: 0,
+        };
+    }
+
+    /// The number of the last line that was read, starting at 1.
+    pub fn line_number(&self) -> usize {
+        return self.line_number;
+    }
+
+    /// Reads the next operation, or `None` at the end of the trace.
+    pub fn next_op(&mut self) -> Result<Option<TraceOp<'_>>> {
+        let len = loop {
+            self.line.clear();
+            let read = self
+                .reader
+                .read_until(b'\n', &mut self.line)
+                .context("Reading trace")?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            let line = trim_line_end(&self.line);
+            if !line.is_empty() && !line.starts_with(b"#") {
+                break line.len();
+            }
+        };
+        return parse_op(&self.line[..len], self.range_format)
+            .map(Some)
+            .with_context(|| format!("Parsing trace line {}", self.line_number));
+    }
+}
+
+/// The line without its line ending.
+fn trim_line_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    return line.strip_suffix(b"\r").unwrap_or(line);
+}
+
+fn parse_op(line: &[u8], range_format: Option<RangeFormat>) -> Result<TraceOp<'_>> {
+    let (code, rest) = split_field(line);
+    let (key, rest) = split_field(rest);
+    if key.is_empty() {
+        bail!("Missing key");
+    }
+    return match code {
+        b"I" => Ok(TraceOp::Insert { key, value: rest }),
+        b"U" => Ok(TraceOp::Update { key, value: rest }),
+        b"M" => Ok(TraceOp::Merge { key, value: rest }),
+        b"D" => Ok(TraceOp::PointDelete { key }),
+        b"P" => Ok(TraceOp::PointQuery { key }),
+        b"R" => Ok(TraceOp::RangeDelete {
+            start: key,
+            end: parse_range_end(key, rest, range_format)?,
+        }),
+        b"S" => Ok(TraceOp::RangeQuery {
+            start: key,
+            end: parse_range_end(key, rest, range_format)?,
+        }),
+        _ => bail!(
+            "Unknown operation {}, expected I, U, M, D, P, R or S",
+            String::from_utf8_lossy(code)
+        ),
+    };
+}
+
+fn parse_range_end<'a>(
+    start: &[u8],
+    end: &'a [u8],
+    range_format: Option<RangeFormat>,
+) -> Result<RangeEnd<'a>> {
+    if end.is_empty() {
+        bail!("Missing end of range");
+    }
+    let is_count = match range_format {
+        Some(RangeFormat::StartCount) => true,
+        Some(RangeFormat::StartEnd) => false,
+        None => is_digits(end) && !is_digits(start),
+    };
+    if !is_count {
+        return Ok(RangeEnd::Key(end));
+    }
+    return std::str::from_utf8(end)
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .map(RangeEnd::Count)
+        .ok_or_else(|| anyhow!("Invalid range count {}", String::from_utf8_lossy(end)));
+}
+
+fn is_digits(field: &[u8]) -> bool {
+    return !field.is_empty() && field.iter().all(u8::is_ascii_digit);
+}
+
+/// Splits off the field before the first space.
+fn split_field(line: &[u8]) -> (&[u8], &[u8]) {
+    return match line.iter().position(|&b| b == b' ') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, &[]),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_op_codes() {
+        assert_eq!(
+            parse_op(b"I key value with spaces", None).unwrap(),
+            TraceOp::Insert {
+                key: b"key",
+                value: b"value with spaces"
+            }
+        );
+        assert_eq!(
+            parse_op(b"U key v", None).unwrap(),
+            TraceOp::Update {
+                key: b"key",
+                value: b"v"
+            }
+        );
+        assert_eq!(
+            parse_op(b"M key v", None).unwrap(),
+            TraceOp::Merge {
+                key: b"key",
+                value: b"v"
+            }
+        );
+        assert_eq!(
+            parse_op(b"D key", None).unwrap(),
+            TraceOp::PointDelete { key: b"key" }
+        );
+        assert_eq!(
+            parse_op(b"P key", None).unwrap(),
+            TraceOp::PointQuery { key: b"key" }
+        );
+        assert!(parse_op(b"X key", None).is_err());
+        assert!(parse_op(b"P", None).is_err());
+        assert!(parse_op(b"S key", None).is_err());
+    }
+
+    #[test]
+    fn parse_op_range_ends() {
+        assert_eq!(
+            parse_op(b"S abc 12", None).unwrap(),
+            TraceOp::RangeQuery {
+                start: b"abc",
+                end: RangeEnd::Count(12)
+            }
+        );
+        // Numeric keys are compared as keys unless the format says otherwise.
+        assert_eq!(
+            parse_op(b"R 10 12", None).unwrap(),
+            TraceOp::RangeDelete {
+                start: b"10",
+                end: RangeEnd::Key(b"12")
+            }
+        );
+        assert_eq!(
+            parse_op(b"R 10 12", Some(RangeFormat::StartCount)).unwrap(),
+            TraceOp::RangeDelete {
+                start: b"10",
+                end: RangeEnd::Count(12)
+            }
+        );
+        assert_eq!(
+            parse_op(b"S abc 12", Some(RangeFormat::StartEnd)).unwrap(),
+            TraceOp::RangeQuery {
+                start: b"abc",
+                end: RangeEnd::Key(b"12")
+            }
+        );
+        assert!(parse_op(b"S abc 1x", Some(RangeFormat::StartCount)).is_err());
+    }
+
+    #[test]
+    fn reader_skips_markers_and_line_endings() {
+        let trace = "# section 0\r\nI a 1\r\n\nP a\n";
+        let mut reader = TraceReader::new(trace.as_bytes(), None);
+        assert_eq!(
+            reader.next_op().unwrap(),
+            Some(TraceOp::Insert {
+                key: b"a",
+                value: b"1"
+            })
+        );
+        assert_eq!(reader.line_number(), 2);
+        assert_eq!(
+            reader.next_op().unwrap(),
+            Some(TraceOp::PointQuery { key: b"a" })
+        );
+        assert_eq!(reader.line_number(), 4);
+        assert_eq!(reader.next_op().unwrap(), None);
+    }
+}